@@ -0,0 +1,296 @@
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use aws_sdk_s3::{
+    primitives::ByteStream,
+    types::{Delete, ObjectIdentifier},
+};
+use bytes::Bytes;
+use clap::{builder::BoolishValueParser, Parser};
+use s3copy::client::endpoint_client;
+use serde::Serialize;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+const KEY_PREFIX: &str = "s3copy-bench/";
+
+/// Measures upload and download throughput against the source or destination endpoint with
+/// dummy objects, which are deleted afterwards.
+///
+/// Each option falls back to the environment variable in brackets.
+#[derive(Parser)]
+#[command(about)]
+struct Args {
+    /// Existing bucket the dummy objects are written to
+    #[arg(long, env = "BENCH_BUCKET")]
+    bucket: String,
+
+    /// Endpoint to measure: old (the source) or new (the destination)
+    #[arg(long, env = "BENCH_TARGET", default_value = "new", value_parser = ["old", "new"])]
+    target: String,
+
+    /// Bytes of each object
+    #[arg(long, env = "BENCH_OBJECT_SIZE", default_value_t = 8 * 1024 * 1024)]
+    object_size: usize,
+
+    /// Number of objects
+    #[arg(long, env = "BENCH_OBJECT_COUNT", default_value_t = 16, value_parser = parse_positive)]
+    object_count: usize,
+
+    /// Requests in flight at once
+    #[arg(long, env = "BENCH_CONCURRENCY", default_value_t = 4, value_parser = parse_positive)]
+    concurrency: usize,
+
+    /// Print the results as JSON
+    #[arg(long, env = "BENCH_JSON", value_parser = BoolishValueParser::new())]
+    json: bool,
+}
+
+/// Parses a count of at least one: no objects leave no latency to take percentiles of, and no
+/// concurrency would wait for a permit forever.
+fn parse_positive(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|value| *value > 0)
+        .ok_or_else(|| format!("must be a positive number: {}", value))
+}
+
+struct BenchResult {
+    operation: &'static str,
+    objects: usize,
+    bytes: usize,
+    elapsed: Duration,
+    latencies: Vec<Duration>,
+}
+
+/// A result in the `--json` output, with the seconds and throughput rounded like the text.
+#[derive(Serialize)]
+struct JsonResult {
+    objects: usize,
+    bytes: usize,
+    seconds: f64,
+    mb_per_s: f64,
+    p50_ms: u128,
+    p90_ms: u128,
+    p99_ms: u128,
+}
+
+#[derive(Serialize)]
+struct JsonOutput {
+    upload: JsonResult,
+    download: JsonResult,
+}
+
+impl BenchResult {
+    fn megabytes_per_second(&self) -> f64 {
+        self.bytes as f64 / 1_000_000.0 / self.elapsed.as_secs_f64()
+    }
+
+    fn percentile_millis(&self, percentile: usize) -> u128 {
+        self.latencies[(self.latencies.len() - 1) * percentile / 100].as_millis()
+    }
+
+    fn to_json(&self) -> JsonResult {
+        JsonResult {
+            objects: self.objects,
+            bytes: self.bytes,
+            seconds: (self.elapsed.as_secs_f64() * 1000.0).round() / 1000.0,
+            mb_per_s: (self.megabytes_per_second() * 100.0).round() / 100.0,
+            p50_ms: self.percentile_millis(50),
+            p90_ms: self.percentile_millis(90),
+            p99_ms: self.percentile_millis(99),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        format!(
+            "{}: {} objects, {} bytes in {:.3}s, {:.2} MB/s, p50 {}ms, p90 {}ms, p99 {}ms",
+            self.operation,
+            self.objects,
+            self.bytes,
+            self.elapsed.as_secs_f64(),
+            self.megabytes_per_second(),
+            self.percentile_millis(50),
+            self.percentile_millis(90),
+            self.percentile_millis(99),
+        )
+    }
+}
+
+async fn run_bench<F, Fut>(
+    operation: &'static str,
+    object_count: usize,
+    object_size: usize,
+    concurrency: usize,
+    request: F,
+) -> BenchResult
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let mut tasks = JoinSet::new();
+    let started = Instant::now();
+    for i in 0..object_count {
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let request = request(format!("{}{}", KEY_PREFIX, i));
+        tasks.spawn(async move {
+            let started = Instant::now();
+            request.await;
+            drop(permit);
+            started.elapsed()
+        });
+    }
+    let mut latencies = Vec::with_capacity(object_count);
+    while let Some(latency) = tasks.join_next().await {
+        latencies.push(latency.unwrap());
+    }
+    latencies.sort_unstable();
+    BenchResult {
+        operation,
+        objects: object_count,
+        bytes: object_count * object_size,
+        elapsed: started.elapsed(),
+        latencies,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let client = endpoint_client(&args.target, "BENCH_TARGET").await;
+
+    let Args {
+        bucket,
+        object_size,
+        object_count,
+        concurrency,
+        ..
+    } = args;
+    let body = Bytes::from(vec![0u8; object_size]);
+
+    let upload = run_bench("upload", object_count, object_size, concurrency, |key| {
+        let request = client
+            .put_object()
+            .bucket(&bucket)
+            .key(key)
+            .body(ByteStream::from(body.clone()));
+        async move {
+            request.send().await.unwrap();
+        }
+    })
+    .await;
+
+    let download = run_bench("download", object_count, object_size, concurrency, |key| {
+        let request = client.get_object().bucket(&bucket).key(key);
+        async move {
+            request.send().await.unwrap().body.collect().await.unwrap();
+        }
+    })
+    .await;
+
+    let keys = (0..object_count)
+        .map(|i| {
+            ObjectIdentifier::builder()
+                .key(format!("{}{}", KEY_PREFIX, i))
+                .build()
+                .unwrap()
+        })
+        .collect::<Vec<ObjectIdentifier>>();
+    for chunk in keys.chunks(1000) {
+        client
+            .delete_objects()
+            .bucket(&bucket)
+            .delete(
+                Delete::builder()
+                    .set_objects(Some(chunk.to_vec()))
+                    .build()
+                    .unwrap(),
+            )
+            .send()
+            .await
+            .unwrap();
+    }
+
+    if args.json {
+        let output = JsonOutput {
+            upload: upload.to_json(),
+            download: download.to_json(),
+        };
+        println!("{}", serde_json::to_string(&output).unwrap());
+    } else {
+        println!("{}", upload.to_text());
+        println!("{}", download.to_text());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn args_are_valid() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_options() {
+        let args = Args::try_parse_from([
+            "bench",
+            "--bucket",
+            "bench-bucket",
+            "--target",
+            "old",
+            "--object-size",
+            "1024",
+            "--object-count",
+            "3",
+            "--concurrency",
+            "2",
+            "--json",
+        ])
+        .unwrap();
+        assert_eq!(args.bucket, "bench-bucket");
+        assert_eq!(args.target, "old");
+        assert_eq!(args.object_size, 1024);
+        assert_eq!(args.object_count, 3);
+        assert_eq!(args.concurrency, 2);
+        assert!(args.json);
+    }
+
+    #[test]
+    fn rejects_zero_counts() {
+        assert!(Args::try_parse_from(["bench", "--bucket", "b", "--object-count", "0"]).is_err());
+        assert!(Args::try_parse_from(["bench", "--bucket", "b", "--concurrency", "0"]).is_err());
+    }
+
+    #[test]
+    fn json_results_are_rounded_like_the_text() {
+        let result = BenchResult {
+            operation: "upload",
+            objects: 2,
+            bytes: 3_000_000,
+            elapsed: Duration::from_micros(1_234_567),
+            latencies: vec![Duration::from_millis(10), Duration::from_millis(20)],
+        };
+        assert_eq!(
+            serde_json::to_string(&result.to_json()).unwrap(),
+            "{\"objects\":2,\"bytes\":3000000,\"seconds\":1.235,\"mb_per_s\":2.43,\
+             \"p50_ms\":10,\"p90_ms\":10,\"p99_ms\":10}"
+        );
+        assert_eq!(
+            result.to_text(),
+            "upload: 2 objects, 3000000 bytes in 1.235s, 2.43 MB/s, p50 10ms, p90 10ms, p99 10ms"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_target() {
+        assert!(Args::try_parse_from(["bench", "--bucket", "b", "--target", "other"]).is_err());
+    }
+}
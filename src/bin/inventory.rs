@@ -0,0 +1,437 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    process,
+};
+
+use aws_sdk_s3::{primitives::DateTimeFormat, Client};
+use clap::{builder::BoolishValueParser, Parser};
+use s3copy::{
+    client::{bucket_client, endpoint_client},
+    listing::{list_destination_objects, ListingRetries},
+    parse_size,
+    upload::{UploadOptions, MIN_PART_SIZE},
+};
+use serde::Serialize;
+
+/// Lists the buckets of an endpoint with their region and creation date, or summarizes the
+/// objects of one bucket by prefix or by size.
+///
+/// Each option falls back to the environment variable in brackets.
+#[derive(Parser)]
+#[command(about)]
+struct Args {
+    /// Endpoint to list: old (the source) or new (the destination)
+    #[arg(long, env = "INVENTORY_TARGET", default_value = "old", value_parser = ["old", "new"])]
+    target: String,
+
+    /// Count the objects and bytes of each bucket, which lists every object
+    #[arg(long, env = "INVENTORY_SIZES", value_parser = BoolishValueParser::new())]
+    sizes: bool,
+
+    /// Print JSON instead of a table
+    #[arg(long, env = "INVENTORY_JSON", value_parser = BoolishValueParser::new())]
+    json: bool,
+
+    /// Instead of listing buckets, count the objects and bytes of --bucket under each prefix of
+    /// this many segments
+    #[arg(long, env = "INVENTORY_PREFIX_DEPTH", requires = "bucket")]
+    prefix_depth: Option<usize>,
+
+    /// Instead of listing buckets, print the distribution of object sizes in --bucket
+    #[arg(
+        long,
+        env = "INVENTORY_HISTOGRAM",
+        value_parser = BoolishValueParser::new(),
+        requires = "bucket"
+    )]
+    histogram: bool,
+
+    /// Bucket summarized by --prefix-depth or --histogram
+    #[arg(long, env = "INVENTORY_BUCKET")]
+    bucket: Option<String>,
+
+    /// Separator of the prefix segments
+    #[arg(long, env = "INVENTORY_DELIMITER", default_value = "/")]
+    delimiter: String,
+
+    /// Multipart threshold of the copy, which --histogram splits the objects by
+    #[arg(
+        long,
+        env = "S3COPY_MULTIPART_THRESHOLD",
+        default_value = "5MiB",
+        value_parser = parse_threshold
+    )]
+    multipart_threshold: usize,
+
+    /// Attempts at each page of an object listing. The same variable as the copy's
+    #[arg(
+        long,
+        env = "LIST_PAGE_ATTEMPTS",
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    list_page_attempts: u32,
+}
+
+fn parse_threshold(value: &str) -> Result<usize, String> {
+    parse_size(value)
+        .map(|bytes| bytes as usize)
+        .ok_or_else(|| format!("invalid size: {}", value))
+}
+
+/// Returns a client for the one bucket summarized by `--prefix-depth` or `--histogram`.
+async fn single_bucket_client(client: &Client, bucket: &str) -> Client {
+    bucket_client(client, &mut HashMap::new(), bucket)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to detect the region of {}: {}", bucket, e))
+        .1
+}
+
+/// Returns the first `depth` delimiter-separated segments of the key's parent prefix.
+fn prefix_at_depth<'a>(key: &'a str, delimiter: &str, depth: usize) -> &'a str {
+    let mut end = 0;
+    for _ in 0..depth {
+        match key[end..].find(delimiter) {
+            Some(i) => end += i + delimiter.len(),
+            None => break,
+        }
+    }
+    &key[..end]
+}
+
+#[derive(Serialize)]
+struct PrefixSummary<'a> {
+    prefix: &'a str,
+    objects: usize,
+    bytes: i64,
+}
+
+async fn print_prefix_sizes(
+    client: &Client,
+    bucket: &str,
+    retries: &ListingRetries,
+    delimiter: &str,
+    depth: usize,
+    json: bool,
+) {
+    let mut prefixes: BTreeMap<String, (usize, i64)> = BTreeMap::new();
+    let objects = list_destination_objects(client, bucket, None, None, retries)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to list {}: {}", bucket, e));
+    for object in objects {
+        let prefix = prefix_at_depth(object.key.as_deref().unwrap(), delimiter, depth);
+        let (objects, bytes) = prefixes.entry(prefix.to_string()).or_default();
+        *objects += 1;
+        *bytes += object.size.unwrap_or_default();
+    }
+
+    if json {
+        let prefixes = prefixes
+            .iter()
+            .map(|(prefix, (objects, bytes))| PrefixSummary {
+                prefix,
+                objects: *objects,
+                bytes: *bytes,
+            })
+            .collect::<Vec<PrefixSummary>>();
+        println!("{}", serde_json::to_string(&prefixes).unwrap());
+    } else {
+        println!("prefix\tobjects\tbytes");
+        for (prefix, (objects, bytes)) in prefixes {
+            println!("{}\t{}\t{}", prefix, objects, bytes);
+        }
+    }
+}
+
+/// Exclusive upper bounds of the histogram buckets. `1MiB-5MiB` includes 5MiB itself so that it
+/// lines up with the default multipart threshold.
+const HISTOGRAM_BUCKETS: [(&str, i64); 3] = [
+    ("<1MiB", 1024 * 1024),
+    ("1MiB-5MiB", MIN_PART_SIZE as i64 + 1),
+    ("5MiB-1GiB", 1024 * 1024 * 1024),
+];
+
+/// Prints the size distribution of the bucket's objects, and how many of them the copy would
+/// upload in parts with this threshold.
+async fn print_size_histogram(
+    client: &Client,
+    bucket: &str,
+    retries: &ListingRetries,
+    multipart_threshold: usize,
+    json: bool,
+) {
+    let upload_options = UploadOptions {
+        multipart_threshold,
+        ..Default::default()
+    };
+    let mut counts = [0usize; HISTOGRAM_BUCKETS.len() + 1];
+    let (mut singlepart, mut multipart) = (0usize, 0usize);
+    let objects = list_destination_objects(client, bucket, None, None, retries)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to list {}: {}", bucket, e));
+    for object in objects {
+        let size = object.size.unwrap_or_default();
+        let index = HISTOGRAM_BUCKETS
+            .iter()
+            .position(|(_, below)| size < *below)
+            .unwrap_or(HISTOGRAM_BUCKETS.len());
+        counts[index] += 1;
+        if upload_options.is_multipart(object.size) {
+            multipart += 1;
+        } else {
+            singlepart += 1;
+        }
+    }
+    let labels = HISTOGRAM_BUCKETS
+        .iter()
+        .map(|(label, _)| *label)
+        .chain([">=1GiB"]);
+
+    if json {
+        let histogram = serde_json::json!({
+            "histogram": labels
+                .zip(counts)
+                .map(|(size, objects)| serde_json::json!({"size": size, "objects": objects}))
+                .collect::<Vec<serde_json::Value>>(),
+            "singlepart": singlepart,
+            "multipart": multipart,
+        });
+        println!("{}", histogram);
+    } else {
+        println!("size\tobjects");
+        for (label, objects) in labels.zip(counts) {
+            println!("{}\t{}", label, objects);
+        }
+        println!("singlepart\t{}", singlepart);
+        println!("multipart\t{}", multipart);
+    }
+}
+
+#[derive(Serialize)]
+struct BucketSummary {
+    name: String,
+    region: String,
+    creation_date: String,
+    objects: Option<usize>,
+    bytes: Option<i64>,
+}
+
+impl BucketSummary {
+    fn to_text(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or("-".to_string());
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.name,
+            self.region,
+            self.creation_date,
+            optional(self.objects.map(|objects| objects.to_string())),
+            optional(self.bytes.map(|bytes| bytes.to_string())),
+        )
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let client = endpoint_client(&args.target, "INVENTORY_TARGET").await;
+    let json = args.json;
+    // Pages are retried like the copy's listing, and a page that still fails ends the listing.
+    let retries = ListingRetries::new(args.list_page_attempts, false);
+
+    // Both modes require the bucket, which clap has checked.
+    if args.histogram {
+        let bucket = args.bucket.as_deref().unwrap();
+        print_size_histogram(
+            &single_bucket_client(&client, bucket).await,
+            bucket,
+            &retries,
+            args.multipart_threshold,
+            json,
+        )
+        .await;
+        return;
+    }
+
+    if let Some(depth) = args.prefix_depth {
+        let bucket = args.bucket.as_deref().unwrap();
+        print_prefix_sizes(
+            &single_bucket_client(&client, bucket).await,
+            bucket,
+            &retries,
+            &args.delimiter,
+            depth,
+            json,
+        )
+        .await;
+        return;
+    }
+
+    let buckets = client
+        .list_buckets()
+        .send()
+        .await
+        .unwrap()
+        .buckets
+        .unwrap_or_default();
+
+    let mut summaries = vec![];
+    let mut regional_clients = HashMap::new();
+    let mut skipped = 0;
+    for bucket in buckets {
+        let name = bucket.name.unwrap();
+        // A bucket that cannot be read is left out, and the rest are still listed.
+        let (region, bucket_client) =
+            match bucket_client(&client, &mut regional_clients, &name).await {
+                Ok(region_and_client) => region_and_client,
+                Err(e) => {
+                    eprintln!("Skipped bucket (region detection failed): {}: {}", name, e);
+                    skipped += 1;
+                    continue;
+                }
+            };
+        let creation_date = bucket
+            .creation_date
+            .and_then(|date| date.fmt(DateTimeFormat::DateTime).ok())
+            .unwrap_or_default();
+        let (objects, bytes) = if args.sizes {
+            let objects =
+                match list_destination_objects(&bucket_client, &name, None, None, &retries).await {
+                    Ok(objects) => objects,
+                    Err(e) => {
+                        eprintln!("Skipped bucket (listing failed): {}: {}", name, e);
+                        skipped += 1;
+                        continue;
+                    }
+                };
+            let bytes = objects
+                .iter()
+                .map(|object| object.size.unwrap_or_default())
+                .sum();
+            (Some(objects.len()), Some(bytes))
+        } else {
+            (None, None)
+        };
+        summaries.push(BucketSummary {
+            name,
+            region,
+            creation_date,
+            objects,
+            bytes,
+        });
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&summaries).unwrap());
+    } else {
+        println!("bucket\tregion\tcreation_date\tobjects\tbytes");
+        for summary in summaries {
+            println!("{}", summary.to_text());
+        }
+    }
+    if skipped > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn args_are_valid() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn defaults_to_listing_the_source() {
+        let args = Args::try_parse_from(["inventory"]).unwrap();
+        assert_eq!(args.target, "old");
+        assert!(!args.sizes && !args.histogram);
+        assert_eq!(args.prefix_depth, None);
+        assert_eq!(args.delimiter, "/");
+        assert_eq!(args.multipart_threshold, MIN_PART_SIZE);
+        assert_eq!(args.list_page_attempts, 3);
+    }
+
+    #[test]
+    fn list_page_attempts_must_be_positive() {
+        let args = Args::try_parse_from(["inventory", "--list-page-attempts", "5"]).unwrap();
+        assert_eq!(args.list_page_attempts, 5);
+        assert!(Args::try_parse_from(["inventory", "--list-page-attempts", "0"]).is_err());
+    }
+
+    #[test]
+    fn parses_multipart_threshold() {
+        let args = Args::try_parse_from([
+            "inventory",
+            "--histogram",
+            "--bucket",
+            "media",
+            "--multipart-threshold",
+            "64MiB",
+        ])
+        .unwrap();
+        assert!(args.histogram);
+        assert_eq!(args.multipart_threshold, 64 * 1024 * 1024);
+        assert!(Args::try_parse_from(["inventory", "--multipart-threshold", "lots"]).is_err());
+    }
+
+    #[test]
+    fn parses_prefix_summary() {
+        let args = Args::try_parse_from([
+            "inventory",
+            "--prefix-depth",
+            "2",
+            "--bucket",
+            "logs",
+            "--delimiter",
+            "-",
+            "--json",
+        ])
+        .unwrap();
+        assert_eq!(args.prefix_depth, Some(2));
+        assert_eq!(args.bucket.as_deref(), Some("logs"));
+        assert_eq!(args.delimiter, "-");
+        assert!(args.json);
+    }
+
+    #[test]
+    fn bucket_modes_require_a_bucket() {
+        assert!(Args::try_parse_from(["inventory", "--histogram"]).is_err());
+        assert!(Args::try_parse_from(["inventory", "--prefix-depth", "1"]).is_err());
+    }
+
+    #[test]
+    fn json_escapes_names() {
+        let summary = BucketSummary {
+            name: "a\"b".to_string(),
+            region: "us-east-1".to_string(),
+            creation_date: String::new(),
+            objects: None,
+            bytes: Some(10),
+        };
+        assert_eq!(
+            serde_json::to_string(&summary).unwrap(),
+            r#"{"name":"a\"b","region":"us-east-1","creation_date":"","objects":null,"bytes":10}"#
+        );
+        let prefix = PrefixSummary {
+            prefix: "日本/\t",
+            objects: 1,
+            bytes: 2,
+        };
+        assert_eq!(
+            serde_json::to_string(&prefix).unwrap(),
+            r#"{"prefix":"日本/\t","objects":1,"bytes":2}"#
+        );
+    }
+
+    #[test]
+    fn prefix_at_depth_stops_at_the_key() {
+        assert_eq!(prefix_at_depth("a/b/c.txt", "/", 1), "a/");
+        assert_eq!(prefix_at_depth("a/b/c.txt", "/", 2), "a/b/");
+        assert_eq!(prefix_at_depth("a/b/c.txt", "/", 5), "a/b/");
+        assert_eq!(prefix_at_depth("root.txt", "/", 1), "");
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_sdk_s3::{primitives::DateTimeFormat, types::RequestPayer};
+use clap::{builder::BoolishValueParser, Parser};
+use s3copy::{
+    client::{bucket_client, endpoint_client},
+    upload::list_multipart_uploads,
+};
+
+/// Lists the incomplete multipart uploads of every bucket, and optionally aborts the old ones.
+///
+/// Each option falls back to the environment variable in brackets.
+#[derive(Parser)]
+#[command(about)]
+struct Args {
+    /// Endpoint to list: old (the source) or new (the destination)
+    #[arg(long, env = "MULTIPART_TARGET", default_value = "new", value_parser = ["old", "new"])]
+    target: String,
+
+    /// Abort the uploads started more than --older-than-hours ago
+    #[arg(long, env = "MULTIPART_ABORT", value_parser = BoolishValueParser::new())]
+    abort: bool,
+
+    /// Hours after which an upload is aborted
+    #[arg(long, env = "MULTIPART_ABORT_OLDER_THAN_HOURS", default_value_t = 24)]
+    older_than_hours: i64,
+
+    /// Only print the uploads that would be aborted
+    #[arg(long, env = "DRY_RUN", value_parser = BoolishValueParser::new())]
+    dry_run: bool,
+
+    /// Abort without asking for confirmation
+    #[arg(long, short, env = "CONFIRM", value_parser = BoolishValueParser::new())]
+    yes: bool,
+
+    /// Send `x-amz-request-payer: requester`, for Requester Pays buckets
+    #[arg(long, env = "REQUEST_PAYER", value_parser = BoolishValueParser::new())]
+    request_payer: bool,
+}
+
+fn confirm(count: usize) -> bool {
+    print!("Abort {} multipart uploads? [y/N] ", count);
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).unwrap();
+    answer.trim().eq_ignore_ascii_case("y")
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let client = endpoint_client(&args.target, "MULTIPART_TARGET").await;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let buckets = client
+        .list_buckets()
+        .send()
+        .await
+        .unwrap()
+        .buckets
+        .unwrap_or_default();
+
+    let request_payer = args.request_payer.then_some(RequestPayer::Requester);
+    let mut regional_clients = HashMap::new();
+    let mut stale_uploads = vec![];
+    let mut skipped = 0;
+    for bucket in buckets {
+        let bucket_name = bucket.name.unwrap();
+        // A bucket that cannot be read is left out, and the rest are still listed.
+        let (_, bucket_client) =
+            match bucket_client(&client, &mut regional_clients, &bucket_name).await {
+                Ok(region_and_client) => region_and_client,
+                Err(e) => {
+                    eprintln!(
+                        "Skipped bucket (region detection failed): {}: {}",
+                        bucket_name, e
+                    );
+                    skipped += 1;
+                    continue;
+                }
+            };
+        let uploads =
+            match list_multipart_uploads(&bucket_client, &bucket_name, request_payer.clone()).await
+            {
+                Ok(uploads) => uploads,
+                Err(e) => {
+                    eprintln!("Skipped bucket (listing failed): {}: {}", bucket_name, e);
+                    skipped += 1;
+                    continue;
+                }
+            };
+        for upload in uploads {
+            println!(
+                "{}/{}\t{}\t{}",
+                bucket_name,
+                upload.key().unwrap_or_default(),
+                upload.upload_id().unwrap_or_default(),
+                upload
+                    .initiated
+                    .and_then(|initiated| initiated.fmt(DateTimeFormat::DateTime).ok())
+                    .unwrap_or_default()
+            );
+            // Without a start date the age is unknown, so the upload is never aborted.
+            if upload
+                .initiated
+                .is_some_and(|initiated| now - initiated.secs() > args.older_than_hours * 60 * 60)
+            {
+                stale_uploads.push((bucket_name.clone(), bucket_client.clone(), upload));
+            }
+        }
+    }
+
+    if !args.abort || stale_uploads.is_empty() {
+        exit(skipped, 0);
+    }
+    if args.dry_run {
+        for (bucket_name, _, upload) in &stale_uploads {
+            println!(
+                "Would abort: {}/{} ({})",
+                bucket_name,
+                upload.key().unwrap_or_default(),
+                upload.upload_id().unwrap_or_default()
+            );
+        }
+        exit(skipped, 0);
+    }
+    if !args.yes && !confirm(stale_uploads.len()) {
+        exit(skipped, 0);
+    }
+    let mut failed = 0;
+    for (bucket_name, bucket_client, upload) in stale_uploads {
+        let upload_name = format!(
+            "{}/{} ({})",
+            bucket_name,
+            upload.key().unwrap_or_default(),
+            upload.upload_id().unwrap_or_default()
+        );
+        println!("Abort: {}", upload_name);
+        if let Err(e) = bucket_client
+            .abort_multipart_upload()
+            .set_request_payer(request_payer.clone())
+            .bucket(&bucket_name)
+            .set_key(upload.key)
+            .set_upload_id(upload.upload_id)
+            .send()
+            .await
+        {
+            eprintln!("Failed to abort: {}: {:?}", upload_name, e);
+            failed += 1;
+        }
+    }
+    exit(skipped, failed);
+}
+
+/// Exits with 1 when a bucket was skipped or an upload could not be aborted, after reporting
+/// how many.
+fn exit(skipped: usize, failed: usize) -> ! {
+    if skipped > 0 {
+        eprintln!("Skipped buckets: {}", skipped);
+    }
+    if failed > 0 {
+        eprintln!("Failed to abort: {} uploads", failed);
+    }
+    process::exit(if skipped > 0 || failed > 0 { 1 } else { 0 });
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn args_are_valid() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn defaults_to_listing_the_destination() {
+        let args = Args::try_parse_from(["multipart"]).unwrap();
+        assert_eq!(args.target, "new");
+        assert!(!args.abort);
+        assert_eq!(args.older_than_hours, 24);
+        assert!(!args.request_payer);
+    }
+
+    #[test]
+    fn parses_abort_options() {
+        let args = Args::try_parse_from([
+            "multipart",
+            "--target",
+            "old",
+            "--abort",
+            "--older-than-hours",
+            "48",
+            "--dry-run",
+            "-y",
+            "--request-payer",
+        ])
+        .unwrap();
+        assert_eq!(args.target, "old");
+        assert!(args.abort);
+        assert_eq!(args.older_than_hours, 48);
+        assert!(args.dry_run);
+        assert!(args.yes);
+        assert!(args.request_payer);
+    }
+}
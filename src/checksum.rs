@@ -0,0 +1,81 @@
+use aws_sdk_s3::{
+    operation::{get_object::GetObjectOutput, put_object::builders::PutObjectFluentBuilder},
+    types::ChecksumAlgorithm,
+};
+use tracing::info;
+
+/// A full-object checksum stored with the source object.
+pub enum Checksum {
+    Crc32(String),
+    Crc32C(String),
+    Sha1(String),
+    Sha256(String),
+}
+
+impl Checksum {
+    /// Returns the source object's checksum if it can be reused for a single `PutObject`.
+    ///
+    /// Checksums of multipart uploads are checksums of the part checksums (`<value>-<parts>`)
+    /// and cannot be sent as a full-object checksum, so they are ignored.
+    pub fn stored(object: &GetObjectOutput) -> Option<Self> {
+        let full_object = |value: &&str| !value.contains('-');
+        if let Some(value) = object.checksum_sha256().filter(full_object) {
+            Some(Checksum::Sha256(value.to_string()))
+        } else if let Some(value) = object.checksum_sha1().filter(full_object) {
+            Some(Checksum::Sha1(value.to_string()))
+        } else if let Some(value) = object.checksum_crc32_c().filter(full_object) {
+            Some(Checksum::Crc32C(value.to_string()))
+        } else {
+            object
+                .checksum_crc32()
+                .filter(full_object)
+                .map(|value| Checksum::Crc32(value.to_string()))
+        }
+    }
+
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Checksum::Crc32(_) => "CRC32",
+            Checksum::Crc32C(_) => "CRC32C",
+            Checksum::Sha1(_) => "SHA1",
+            Checksum::Sha256(_) => "SHA256",
+        }
+    }
+
+    /// The base64-encoded checksum, as S3 returns it.
+    pub fn value(&self) -> &str {
+        match self {
+            Checksum::Crc32(value)
+            | Checksum::Crc32C(value)
+            | Checksum::Sha1(value)
+            | Checksum::Sha256(value) => value,
+        }
+    }
+
+    pub fn apply(self, request: PutObjectFluentBuilder) -> PutObjectFluentBuilder {
+        match self {
+            Checksum::Crc32(value) => request.checksum_crc32(value),
+            Checksum::Crc32C(value) => request.checksum_crc32_c(value),
+            Checksum::Sha1(value) => request.checksum_sha1(value),
+            Checksum::Sha256(value) => request.checksum_sha256(value),
+        }
+    }
+}
+
+/// Sets the source's stored checksum on the upload, or asks the SDK to compute a CRC32 when
+/// the source has none, and prints which of the two happened.
+pub fn with_checksum(
+    request: PutObjectFluentBuilder,
+    object: &GetObjectOutput,
+) -> PutObjectFluentBuilder {
+    match Checksum::stored(object) {
+        Some(checksum) => {
+            info!("Checksum: preserved {}", checksum.algorithm());
+            checksum.apply(request)
+        }
+        None => {
+            info!("Checksum: recomputed CRC32");
+            request.checksum_algorithm(ChecksumAlgorithm::Crc32)
+        }
+    }
+}
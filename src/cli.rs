@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use clap::{builder::BoolishValueParser, Parser};
+use s3copy::{
+    client::{parse_seconds, ClientSettings, NEW_CREDENTIALS_FILE, OLD_CREDENTIALS_FILE},
+    parse_size,
+};
+
+/// Copies every bucket of one S3-compatible endpoint to another.
+///
+/// Each option falls back to the environment variable in brackets. Settings without an option
+/// are read from environment variables only; see the README for the full list.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Region of the source endpoint
+    #[arg(long, env = "OLD_AWS_REGION", default_value = "us-east-1")]
+    pub old_region: String,
+
+    /// Region of the destination endpoint
+    #[arg(long, env = "NEW_AWS_REGION", default_value = "us-east-1")]
+    pub new_region: String,
+
+    /// Endpoint URL of the source (AWS when unset)
+    #[arg(long, env = "OLD_AWS_ENDPOINT_URL")]
+    pub old_endpoint: Option<String>,
+
+    /// Endpoint URL of the destination (AWS when unset)
+    #[arg(long, env = "NEW_AWS_ENDPOINT_URL")]
+    pub new_endpoint: Option<String>,
+
+    /// Credentials file of the source
+    #[arg(long, env = "OLD_CREDENTIALS_FILE", default_value = OLD_CREDENTIALS_FILE)]
+    pub old_credentials: String,
+
+    /// Credentials file of the destination
+    #[arg(long, env = "NEW_CREDENTIALS_FILE", default_value = NEW_CREDENTIALS_FILE)]
+    pub new_credentials: String,
+
+    /// Seconds allowed to connect to the source
+    #[arg(long, env = "OLD_CONNECT_TIMEOUT", value_parser = parse_seconds)]
+    pub old_connect_timeout: Option<Duration>,
+
+    /// Seconds allowed between reads of a response from the source
+    #[arg(long, env = "OLD_READ_TIMEOUT", value_parser = parse_seconds)]
+    pub old_read_timeout: Option<Duration>,
+
+    /// Seconds allowed for a single attempt of a request to the source
+    #[arg(long, env = "OLD_ATTEMPT_TIMEOUT", value_parser = parse_seconds)]
+    pub old_attempt_timeout: Option<Duration>,
+
+    /// Attempts of a request to the source, including the first one
+    #[arg(long, env = "OLD_MAX_ATTEMPTS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub old_max_attempts: Option<u32>,
+
+    /// PEM file of CA certificates trusted for the source, in addition to the system's
+    #[arg(long, env = "OLD_CA_BUNDLE")]
+    pub old_ca_bundle: Option<String>,
+
+    /// Seconds allowed to connect to the destination
+    #[arg(long, env = "NEW_CONNECT_TIMEOUT", value_parser = parse_seconds)]
+    pub new_connect_timeout: Option<Duration>,
+
+    /// Seconds allowed between reads of a response from the destination
+    #[arg(long, env = "NEW_READ_TIMEOUT", value_parser = parse_seconds)]
+    pub new_read_timeout: Option<Duration>,
+
+    /// Seconds allowed for a single attempt of a request to the destination
+    #[arg(long, env = "NEW_ATTEMPT_TIMEOUT", value_parser = parse_seconds)]
+    pub new_attempt_timeout: Option<Duration>,
+
+    /// Attempts of a request to the destination, including the first one
+    #[arg(long, env = "NEW_MAX_ATTEMPTS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub new_max_attempts: Option<u32>,
+
+    /// PEM file of CA certificates trusted for the destination, in addition to the system's
+    #[arg(long, env = "NEW_CA_BUNDLE")]
+    pub new_ca_bundle: Option<String>,
+
+    /// Bytes that uploads may hold in memory at once across every object and part, such as
+    /// 2GiB
+    #[arg(long, env = "MAX_INFLIGHT_BYTES", value_parser = parse_bytes)]
+    pub max_inflight_bytes: Option<usize>,
+
+    /// Requests carrying object data (uploads of objects and parts, and server-side copies) in
+    /// flight at once, across every bucket and object
+    #[arg(long, env = "MAX_TRANSFERS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_transfers: Option<u64>,
+
+    /// Metadata requests (HeadObject and GetObjectTagging) in flight at once, apart from
+    /// --max-transfers
+    #[arg(long, env = "HEAD_CONCURRENCY", value_parser = clap::value_parser!(u64).range(1..))]
+    pub head_concurrency: Option<u64>,
+
+    /// Suffix added to a bucket name that is already taken at the destination
+    #[arg(long, env = "NEW_BUCKET_SUFFIX", allow_hyphen_values = true)]
+    pub bucket_suffix: Option<String>,
+
+    /// TOML file listing the buckets to copy, with their destinations and options
+    #[arg(long, env = "S3COPY_CONFIG")]
+    pub config: Option<String>,
+
+    /// JSON Lines file of the objects already copied, read to skip them and appended to as each
+    /// object is copied
+    #[arg(long, env = "STATE_FILE")]
+    pub state_file: Option<String>,
+
+    /// Show a dashboard of the copy instead of logs (needs the tui feature and a terminal)
+    #[arg(long, env = "TUI", value_parser = BoolishValueParser::new())]
+    pub tui: bool,
+
+    /// Export a span per bucket and object to this OTLP/HTTP endpoint, such as
+    /// http://localhost:4318/v1/traces (needs the otlp feature)
+    #[arg(long, env = "OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Stop the run with exit code 3 once this many objects have failed
+    #[arg(long, env = "ABORT_ON_FAILURES", value_parser = clap::value_parser!(u64).range(1..))]
+    pub abort_on_failures: Option<u64>,
+
+    /// How failures are counted against --abort-on-failures: in a row, or over the whole run
+    #[arg(
+        long,
+        env = "ABORT_ON_FAILURES_COUNT",
+        value_parser = ["consecutive", "total"],
+        default_value = "consecutive"
+    )]
+    pub abort_on_failures_count: String,
+
+    /// Only list and compare, without changing the destination
+    #[arg(long, env = "DRY_RUN", value_parser = BoolishValueParser::new())]
+    pub dry_run: bool,
+
+    /// Print the final summary as one line of JSON
+    #[arg(long, env = "SUMMARY_JSON", value_parser = BoolishValueParser::new())]
+    pub json: bool,
+}
+
+fn parse_bytes(value: &str) -> Result<usize, String> {
+    parse_size(value)
+        .filter(|bytes| *bytes > 0)
+        .map(|bytes| bytes as usize)
+        .ok_or_else(|| format!("invalid size: {}", value))
+}
+
+impl Cli {
+    /// Connection settings of the source endpoint.
+    pub fn old_settings(&self) -> ClientSettings {
+        ClientSettings {
+            connect_timeout: self.old_connect_timeout,
+            read_timeout: self.old_read_timeout,
+            attempt_timeout: self.old_attempt_timeout,
+            max_attempts: self.old_max_attempts,
+            ca_bundle: self.old_ca_bundle.clone(),
+        }
+    }
+
+    /// Connection settings of the destination endpoint.
+    pub fn new_settings(&self) -> ClientSettings {
+        ClientSettings {
+            connect_timeout: self.new_connect_timeout,
+            read_timeout: self.new_read_timeout,
+            attempt_timeout: self.new_attempt_timeout,
+            max_attempts: self.new_max_attempts,
+            ca_bundle: self.new_ca_bundle.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn cli_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_endpoints_and_credentials() {
+        let cli = Cli::try_parse_from([
+            "s3copy",
+            "--old-region",
+            "ap-northeast-1",
+            "--new-region",
+            "eu-central-1",
+            "--old-endpoint",
+            "https://old.example.com",
+            "--new-endpoint",
+            "https://new.example.com",
+            "--old-credentials",
+            "old.ini",
+            "--new-credentials",
+            "new.ini",
+            "--bucket-suffix",
+            "-copy",
+            "--dry-run",
+            "--json",
+            "--abort-on-failures",
+            "10",
+            "--abort-on-failures-count",
+            "total",
+        ])
+        .unwrap();
+        assert_eq!(cli.old_region, "ap-northeast-1");
+        assert_eq!(cli.new_region, "eu-central-1");
+        assert_eq!(cli.old_endpoint.as_deref(), Some("https://old.example.com"));
+        assert_eq!(cli.new_endpoint.as_deref(), Some("https://new.example.com"));
+        assert_eq!(cli.old_credentials, "old.ini");
+        assert_eq!(cli.new_credentials, "new.ini");
+        assert_eq!(cli.bucket_suffix.as_deref(), Some("-copy"));
+        assert!(cli.dry_run);
+        assert!(cli.json);
+        assert_eq!(cli.abort_on_failures, Some(10));
+        assert_eq!(cli.abort_on_failures_count, "total");
+    }
+
+    #[test]
+    fn parses_connection_settings() {
+        let cli = Cli::try_parse_from([
+            "s3copy",
+            "--new-connect-timeout",
+            "2.5",
+            "--new-max-attempts",
+            "4",
+            "--max-inflight-bytes",
+            "1GiB",
+        ])
+        .unwrap();
+        let settings = cli.new_settings();
+        assert_eq!(settings.connect_timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(settings.max_attempts, Some(4));
+        assert_eq!(cli.old_settings().max_attempts, None);
+        assert_eq!(cli.max_inflight_bytes, Some(1 << 30));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(Cli::try_parse_from(["s3copy", "--new-max-attempts", "0"]).is_err());
+        assert!(Cli::try_parse_from(["s3copy", "--max-inflight-bytes", "0"]).is_err());
+        assert!(Cli::try_parse_from(["s3copy", "--max-transfers", "0"]).is_err());
+        assert!(Cli::try_parse_from(["s3copy", "--head-concurrency", "0"]).is_err());
+        assert!(Cli::try_parse_from(["s3copy", "--old-read-timeout", "soon"]).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::{collections::HashMap, env, fs, time::Duration};
+
+use aws_config::Region;
+use aws_runtime::env_config::file::{EnvConfigFileKind, EnvConfigFiles};
+use aws_sdk_s3::{
+    config::{retry::RetryConfig, timeout::TimeoutConfig, Builder, SharedHttpClient},
+    types::BucketLocationConstraint,
+    Client,
+};
+use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
+use rustls::{Certificate, ClientConfig, RootCertStore};
+
+/// Credentials file of the source endpoint.
+pub const OLD_CREDENTIALS_FILE: &str = ".old.credentials";
+/// Credentials file of the destination endpoint.
+pub const NEW_CREDENTIALS_FILE: &str = ".new.credentials";
+
+/// Profile files made of only the given credentials file.
+pub fn credentials_file(path: &str) -> EnvConfigFiles {
+    EnvConfigFiles::builder()
+        .with_file(EnvConfigFileKind::Credentials, path)
+        .build()
+}
+
+/// Connection settings of one endpoint. Unset fields keep the SDK defaults.
+#[derive(Clone, Default)]
+pub struct ClientSettings {
+    /// Time allowed to establish a connection.
+    pub connect_timeout: Option<Duration>,
+    /// Time allowed between reads of a response.
+    pub read_timeout: Option<Duration>,
+    /// Time allowed for a single attempt of a request, including reading its response.
+    pub attempt_timeout: Option<Duration>,
+    /// Attempts of a request, including the first one.
+    pub max_attempts: Option<u32>,
+    /// PEM file of CA certificates trusted in addition to the system's, for endpoints with a
+    /// private CA.
+    pub ca_bundle: Option<String>,
+}
+
+impl ClientSettings {
+    /// Reads `{prefix}_CONNECT_TIMEOUT`, `_READ_TIMEOUT`, `_ATTEMPT_TIMEOUT` (seconds),
+    /// `_MAX_ATTEMPTS` and `_CA_BUNDLE`.
+    pub fn from_env(prefix: &str) -> Self {
+        let seconds = |name: &str| {
+            let variable = format!("{}_{}", prefix, name);
+            env::var(&variable).ok().map(|value| {
+                parse_seconds(&value).unwrap_or_else(|e| panic!("{}: {}", variable, e))
+            })
+        };
+        ClientSettings {
+            connect_timeout: seconds("CONNECT_TIMEOUT"),
+            read_timeout: seconds("READ_TIMEOUT"),
+            attempt_timeout: seconds("ATTEMPT_TIMEOUT"),
+            max_attempts: env::var(format!("{}_MAX_ATTEMPTS", prefix))
+                .ok()
+                .map(|attempts| {
+                    attempts
+                        .parse()
+                        .ok()
+                        .filter(|attempts| *attempts > 0)
+                        .unwrap_or_else(|| {
+                            panic!("{}_MAX_ATTEMPTS must be a positive number", prefix)
+                        })
+                }),
+            ca_bundle: env::var(format!("{}_CA_BUNDLE", prefix)).ok(),
+        }
+    }
+}
+
+/// Parses a duration in seconds, such as `30` or `2.5`.
+pub fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("invalid number of seconds: {}", value))
+}
+
+/// An HTTPS client trusting the certificates of `ca_bundle` as well as the system's.
+fn http_client(ca_bundle: &str) -> SharedHttpClient {
+    let mut roots = RootCertStore::empty();
+    for certificate in rustls_native_certs::load_native_certs()
+        .unwrap_or_else(|e| panic!("Failed to load the system certificates: {}", e))
+    {
+        // The system store may hold certificates rustls does not accept; the SDK skips them too.
+        let _ = roots.add(&Certificate(certificate.0));
+    }
+    let pem = fs::read(ca_bundle).unwrap_or_else(|e| panic!("Failed to read {}: {}", ca_bundle, e));
+    let certificates = rustls_pemfile::certs(&mut pem.as_slice())
+        .unwrap_or_else(|e| panic!("Invalid CA bundle {}: {}", ca_bundle, e));
+    if certificates.is_empty() {
+        panic!("No certificates found in {}", ca_bundle);
+    }
+    for certificate in certificates {
+        roots
+            .add(&Certificate(certificate))
+            .unwrap_or_else(|e| panic!("Invalid certificate in {}: {}", ca_bundle, e));
+    }
+    let tls_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    HyperClientBuilder::new().build(
+        hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls_config)
+            .https_or_http()
+            .enable_http1()
+            .enable_http2()
+            .build(),
+    )
+}
+
+/// Builds a client for the endpoint (AWS when `None`). Path-style addressing is forced, since
+/// S3-compatible stores often do not support virtual-hosted buckets.
+pub async fn get_client(
+    env_config_files: EnvConfigFiles,
+    region: Region,
+    endpoint_url: Option<&str>,
+    settings: &ClientSettings,
+) -> Client {
+    let mut config_loader = aws_config::from_env()
+        .profile_files(env_config_files)
+        .region(region);
+    config_loader = match endpoint_url {
+        Some(url) => config_loader.endpoint_url(url),
+        None => config_loader,
+    };
+    if let Some(ca_bundle) = &settings.ca_bundle {
+        config_loader = config_loader.http_client(http_client(ca_bundle));
+    }
+    let sdk_config = config_loader.load().await;
+
+    let mut timeouts = TimeoutConfig::builder();
+    timeouts
+        .set_connect_timeout(settings.connect_timeout)
+        .set_read_timeout(settings.read_timeout)
+        .set_operation_attempt_timeout(settings.attempt_timeout);
+    let timeouts = timeouts
+        .take_unset_from(
+            sdk_config
+                .timeout_config()
+                .map(TimeoutConfig::to_builder)
+                .unwrap_or_default(),
+        )
+        .build();
+    let mut retries = sdk_config
+        .retry_config()
+        .cloned()
+        .unwrap_or_else(RetryConfig::standard);
+    if let Some(max_attempts) = settings.max_attempts {
+        retries = retries.with_max_attempts(max_attempts);
+    }
+
+    let config = Builder::from(&sdk_config)
+        .force_path_style(true)
+        .timeout_config(timeouts)
+        .retry_config(retries)
+        .build();
+    Client::from_conf(config)
+}
+
+/// Accepts any region identifier such as `eu-west-1`, rejecting only strings that cannot be one.
+pub fn region_from_str(region: &str) -> Region {
+    if region.is_empty()
+        || !region
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        panic!("Invalid region: {:?}", region);
+    }
+    Region::new(region.to_string())
+}
+
+/// Returns the bucket's region, or the error as text when it cannot be detected (e.g. when
+/// `GetBucketLocation` is denied).
+pub async fn bucket_region(client: &Client, bucket: &str) -> Result<String, String> {
+    let location = client
+        .get_bucket_location()
+        .bucket(bucket)
+        .send()
+        .await
+        .map_err(|e| format!("{:?}", e))?
+        .location_constraint;
+    Ok(
+        match location.as_ref().map(BucketLocationConstraint::as_str) {
+            None | Some("") => "us-east-1".to_string(),
+            Some("EU") => "eu-west-1".to_string(),
+            Some(region) => region.to_string(),
+        },
+    )
+}
+
+/// The client with its region replaced, for the buckets of another region. Credentials, endpoint
+/// and connection settings are shared with `client`.
+pub fn regional_client(client: &Client, region: &str) -> Client {
+    Client::from_conf(
+        client
+            .config()
+            .to_builder()
+            .region(Region::new(region.to_string()))
+            .build(),
+    )
+}
+
+/// Returns the bucket's region and a client for it, reusing the clients of regions already seen.
+/// Buckets outside the client's region can only be listed through a client of their own region.
+pub async fn bucket_client(
+    client: &Client,
+    regional_clients: &mut HashMap<String, Client>,
+    bucket: &str,
+) -> Result<(String, Client), String> {
+    let region = bucket_region(client, bucket).await?;
+    if client.config().region().map(|region| region.as_ref()) == Some(region.as_str()) {
+        return Ok((region, client.clone()));
+    }
+    let client = regional_clients
+        .entry(region.clone())
+        .or_insert_with(|| regional_client(client, &region))
+        .clone();
+    Ok((region, client))
+}
+
+/// Client for the source (`old`) or destination (`new`) endpoint, configured from the same
+/// `OLD_`/`NEW_` variables as the copy, connection settings included. `variable` is the setting
+/// that chose the target, named when it is neither.
+pub async fn endpoint_client(target: &str, variable: &str) -> Client {
+    let (credentials, prefix) = match target {
+        "old" => (OLD_CREDENTIALS_FILE, "OLD"),
+        "new" => (NEW_CREDENTIALS_FILE, "NEW"),
+        _ => panic!("{} must be old or new", variable),
+    };
+    get_client(
+        credentials_file(
+            &env::var(format!("{}_CREDENTIALS_FILE", prefix)).unwrap_or(credentials.to_string()),
+        ),
+        region_from_str(
+            env::var(format!("{}_AWS_REGION", prefix))
+                .unwrap_or("us-east-1".to_string())
+                .as_str(),
+        ),
+        env::var(format!("{}_AWS_ENDPOINT_URL", prefix))
+            .ok()
+            .as_deref(),
+        &ClientSettings::from_env(prefix),
+    )
+    .await
+}
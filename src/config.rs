@@ -0,0 +1,181 @@
+use std::fs;
+
+use aws_sdk_s3::types::StorageClass;
+use serde::Deserialize;
+
+/// Settings of every bucket entry that does not set its own.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    prefix: Option<String>,
+    storage_class: Option<String>,
+    object_concurrency: Option<usize>,
+}
+
+/// A source bucket and how it is copied.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    pub source: String,
+    /// Destination bucket name, the source's name when unset.
+    pub dest: Option<String>,
+    /// Only objects under this prefix are copied.
+    pub prefix: Option<String>,
+    /// Storage class of every copied object, overriding `STORAGE_CLASS_BY_SIZE` and
+    /// `PRESERVE_STORAGE_CLASS`.
+    pub storage_class: Option<String>,
+    /// Objects of this bucket copied at once, overriding `OBJECT_CONCURRENCY`. Their transfers
+    /// still count against `MAX_TRANSFERS`.
+    pub object_concurrency: Option<usize>,
+}
+
+impl BucketConfig {
+    /// The entry of a listed bucket, copied under its own name with the global settings.
+    pub fn listed(source: String) -> Self {
+        BucketConfig {
+            source,
+            dest: None,
+            prefix: None,
+            storage_class: None,
+            object_concurrency: None,
+        }
+    }
+
+    pub fn dest(&self) -> &str {
+        self.dest.as_deref().unwrap_or(&self.source)
+    }
+
+    pub fn storage_class(&self) -> Option<StorageClass> {
+        self.storage_class.as_deref().map(StorageClass::from)
+    }
+}
+
+/// A TOML file listing the buckets to copy, used instead of listing the source's buckets.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    defaults: Defaults,
+    buckets: Vec<BucketConfig>,
+}
+
+impl Config {
+    /// Reads the config and returns its bucket entries with the defaults filled in.
+    pub fn load(path: &str) -> Vec<BucketConfig> {
+        let contents =
+            fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+        let config: Config =
+            toml::from_str(&contents).unwrap_or_else(|e| panic!("Invalid config {}: {}", path, e));
+        config
+            .buckets
+            .into_iter()
+            .map(|mut bucket| {
+                bucket.prefix = bucket.prefix.or_else(|| config.defaults.prefix.clone());
+                bucket.storage_class = bucket
+                    .storage_class
+                    .or_else(|| config.defaults.storage_class.clone());
+                bucket.object_concurrency = bucket
+                    .object_concurrency
+                    .or(config.defaults.object_concurrency);
+                if bucket.object_concurrency == Some(0) {
+                    panic!(
+                        "object_concurrency in {} must be a positive number: {}",
+                        path, bucket.source
+                    );
+                }
+                if let Some(storage_class) = &bucket.storage_class {
+                    if !StorageClass::values().contains(&storage_class.as_str()) {
+                        panic!(
+                            "Invalid storage class in {}: {} (expected one of {})",
+                            path,
+                            storage_class,
+                            StorageClass::values().join(", ")
+                        );
+                    }
+                }
+                bucket
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, panic, process};
+
+    use super::*;
+
+    fn load(name: &str, contents: &str) -> std::thread::Result<Vec<BucketConfig>> {
+        let path = env::temp_dir().join(format!("s3copy-{}-{}.toml", name, process::id()));
+        fs::write(&path, contents).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let buckets = panic::catch_unwind(|| Config::load(&path));
+        fs::remove_file(&path).unwrap();
+        buckets
+    }
+
+    #[test]
+    fn defaults_fill_in_what_entries_leave_unset() {
+        let buckets = load(
+            "config",
+            r#"
+            [defaults]
+            prefix = "2024/"
+            storage_class = "STANDARD_IA"
+            object_concurrency = 8
+
+            [[buckets]]
+            source = "logs"
+
+            [[buckets]]
+            source = "photos"
+            dest = "photos-archive"
+            prefix = "raw/"
+            storage_class = "GLACIER"
+            object_concurrency = 2
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].dest(), "logs");
+        assert_eq!(buckets[0].prefix.as_deref(), Some("2024/"));
+        assert_eq!(buckets[0].storage_class(), Some(StorageClass::StandardIa));
+        assert_eq!(buckets[0].object_concurrency, Some(8));
+        assert_eq!(buckets[1].dest(), "photos-archive");
+        assert_eq!(buckets[1].prefix.as_deref(), Some("raw/"));
+        assert_eq!(buckets[1].storage_class(), Some(StorageClass::Glacier));
+        assert_eq!(buckets[1].object_concurrency, Some(2));
+    }
+
+    #[test]
+    fn defaults_are_optional() {
+        let buckets = load("bare-config", "[[buckets]]\nsource = \"logs\"\n").unwrap();
+
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].prefix, None);
+        assert_eq!(buckets[0].storage_class(), None);
+        assert_eq!(buckets[0].object_concurrency, None);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        for (name, contents) in [
+            (
+                "bad-storage-class",
+                "[[buckets]]\nsource = \"logs\"\nstorage_class = \"COLD\"\n",
+            ),
+            (
+                "zero-concurrency",
+                "[defaults]\nobject_concurrency = 0\n[[buckets]]\nsource = \"logs\"\n",
+            ),
+            (
+                "unknown-field",
+                "[[buckets]]\nsource = \"logs\"\ndestination = \"archive\"\n",
+            ),
+            ("no-buckets", "[defaults]\nprefix = \"2024/\"\n"),
+        ] {
+            assert!(load(name, contents).is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use aws_sdk_s3::{
+    config::ProvideCredentials,
+    types::{CompletedMultipartUpload, CompletedPart, Object, ObjectStorageClass},
+    Client,
+};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use tracing::info;
+
+use crate::{
+    client::credentials_file,
+    upload::{
+        abort_upload, checked_expires, part_size, validate_parts, verify_size, UploadOptions,
+        MAX_SINGLEPART_SIZE,
+    },
+};
+
+const COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+const TAGGING_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// The `CopySource` value of the object, with the key percent-encoded.
+pub fn copy_source(bucket: &str, key: &str) -> String {
+    format!(
+        "{}/{}",
+        bucket,
+        utf8_percent_encode(key, COPY_SOURCE_ENCODE_SET)
+    )
+}
+
+/// Returns the object's tags as a `Tagging` value (`key=value&...`), or `None` when it has none.
+pub async fn object_tagging(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    options: &UploadOptions,
+) -> Result<Option<String>, String> {
+    let _head = options.reserve_head().await;
+    let tag_set = client
+        .get_object_tagging()
+        .set_request_payer(options.request_payer.clone())
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| format!("{:?}", e))?
+        .tag_set;
+    Ok((!tag_set.is_empty()).then(|| {
+        tag_set
+            .iter()
+            .map(|tag| {
+                format!(
+                    "{}={}",
+                    utf8_percent_encode(tag.key(), TAGGING_ENCODE_SET),
+                    utf8_percent_encode(tag.value(), TAGGING_ENCODE_SET)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("&")
+    }))
+}
+
+async fn access_key_id(path: &str) -> Option<String> {
+    let config = aws_config::from_env()
+        .profile_files(credentials_file(path))
+        .load()
+        .await;
+    let credentials = config
+        .credentials_provider()?
+        .provide_credentials()
+        .await
+        .ok()?;
+    Some(credentials.access_key_id().to_string())
+}
+
+/// Whether the source and destination credentials files resolve to the same access key, so that
+/// the destination's credentials can read the source bucket.
+pub async fn same_credentials(old_credentials: &str, new_credentials: &str) -> bool {
+    let old = access_key_id(old_credentials).await;
+    old.is_some() && old == access_key_id(new_credentials).await
+}
+
+/// Copies the object inside the endpoint without streaming it through this process, and returns
+/// whether it was copied in parts.
+///
+/// `CopyObject` keeps the source's content headers, metadata and tags, but is limited to 5GiB.
+/// Larger objects are copied with `UploadPartCopy`, which copies none of them, so they are read
+/// from the source and set on the multipart upload.
+pub async fn server_side_copy(
+    old_client: &Client,
+    new_client: &Client,
+    bucket: &str,
+    object: &Object,
+    new_bucket: &str,
+    new_key: &str,
+    options: &UploadOptions,
+) -> Result<bool, String> {
+    let key = object.key.as_deref().unwrap();
+    let object_size = object.size.unwrap_or_default();
+    let storage_class = options.storage_class(
+        object_size,
+        object
+            .storage_class
+            .as_ref()
+            .map(ObjectStorageClass::as_str),
+    );
+
+    if object_size as usize <= MAX_SINGLEPART_SIZE {
+        let transfer = options.reserve_transfer().await;
+        new_client
+            .copy_object()
+            .set_request_payer(options.request_payer.clone())
+            .copy_source(copy_source(bucket, key))
+            .bucket(new_bucket)
+            .key(new_key)
+            .set_acl(options.acl.clone())
+            .set_storage_class(storage_class)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        drop(transfer);
+        if options.verify_size {
+            verify_size(new_client, new_bucket, new_key, object.size, options).await?;
+        }
+        return Ok(false);
+    }
+
+    let head = {
+        let _head = options.reserve_head().await;
+        old_client
+            .head_object()
+            .set_request_payer(options.request_payer.clone())
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?
+    };
+    let tagging = object_tagging(old_client, bucket, key, options).await?;
+    let upload_id = new_client
+        .create_multipart_upload()
+        .set_request_payer(options.request_payer.clone())
+        .bucket(new_bucket)
+        .key(new_key)
+        .set_acl(options.acl.clone())
+        .set_storage_class(storage_class)
+        .set_content_type(head.content_type.clone())
+        .set_content_encoding(head.content_encoding.clone())
+        .set_cache_control(head.cache_control.clone())
+        .set_content_disposition(head.content_disposition.clone())
+        .set_expires(checked_expires(
+            head.expires_string.as_deref(),
+            key,
+            options,
+        )?)
+        .set_metadata(
+            head.metadata
+                .clone()
+                .filter(|metadata| !metadata.is_empty()),
+        )
+        .set_tagging(tagging)
+        .send()
+        .await
+        .map_err(|e| format!("{:?}", e))?
+        .upload_id
+        .ok_or_else(|| format!("No upload ID was returned for {}", new_key))?;
+    options
+        .open_uploads
+        .open(new_client, new_bucket, new_key, &upload_id);
+
+    let part_size = part_size(
+        object_size as usize,
+        options.part_size,
+        &options.part_size_tiers,
+    ) as i64;
+    let source = copy_source(bucket, key);
+    let part_count = (object_size as usize).div_ceil(part_size as usize);
+    let parts = stream::iter((0..object_size).step_by(part_size as usize).enumerate())
+        .map(|(index, start)| {
+            let request = new_client
+                .upload_part_copy()
+                .set_request_payer(options.request_payer.clone())
+                .copy_source(&source)
+                .copy_source_range(format!(
+                    "bytes={}-{}",
+                    start,
+                    (start + part_size).min(object_size) - 1
+                ))
+                .bucket(new_bucket)
+                .key(new_key)
+                .upload_id(&upload_id)
+                .part_number(index as i32 + 1);
+            async move {
+                let transfer = options.reserve_transfer().await;
+                let output = request.send().await.map_err(|e| format!("{:?}", e))?;
+                drop(transfer);
+                let e_tag = output
+                    .copy_part_result
+                    .and_then(|result| result.e_tag)
+                    .ok_or_else(|| {
+                        format!(
+                            "Part {} of {} was copied without an ETag, so the upload cannot be \
+                             completed",
+                            index + 1,
+                            new_key
+                        )
+                    })?;
+                Ok::<CompletedPart, String>(
+                    CompletedPart::builder()
+                        .e_tag(e_tag)
+                        .part_number(index as i32 + 1)
+                        .build(),
+                )
+            }
+        })
+        .buffer_unordered(options.max_concurrent_parts)
+        .try_collect::<Vec<CompletedPart>>()
+        .await
+        .and_then(|mut parts| {
+            validate_parts(new_key, &mut parts, part_count)?;
+            Ok(parts)
+        });
+    let parts = match parts {
+        Ok(parts) => parts,
+        Err(e) => {
+            abort_upload(new_client, new_bucket, new_key, &upload_id, options).await;
+            return Err(e);
+        }
+    };
+    info!("Server-side copy: {} parts", parts.len());
+
+    if let Err(e) = new_client
+        .complete_multipart_upload()
+        .set_request_payer(options.request_payer.clone())
+        .bucket(new_bucket)
+        .key(new_key)
+        .upload_id(&upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .send()
+        .await
+    {
+        abort_upload(new_client, new_bucket, new_key, &upload_id, options).await;
+        return Err(format!("{:?}", e));
+    }
+    options.open_uploads.close(&upload_id);
+    if options.verify_size {
+        verify_size(new_client, new_bucket, new_key, object.size, options).await?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use aws_sdk_s3::types::ObjectCannedAcl;
+
+    use super::*;
+    use crate::fake_s3::{FakeS3, StoredObject};
+
+    fn source() -> StoredObject {
+        StoredObject {
+            content: b"a long video clip".to_vec(),
+            headers: BTreeMap::from([
+                ("content-type".to_string(), "video/mp4".to_string()),
+                ("x-amz-meta-camera".to_string(), "front door".to_string()),
+            ]),
+            tagging: Some("env=prod&team=data".to_string()),
+            acl: None,
+        }
+    }
+
+    fn listed(key: &str, size: i64) -> Object {
+        Object::builder().key(key).size(size).build()
+    }
+
+    #[test]
+    fn copy_sources_percent_encode_the_key() {
+        assert_eq!(
+            copy_source("photos", "2024/my cat+dog.jpg"),
+            "photos/2024/my%20cat%2Bdog.jpg"
+        );
+    }
+
+    #[tokio::test]
+    async fn small_objects_are_copied_with_copy_object() {
+        let s3 = FakeS3::default().with_stored_object("videos", "clip.mp4", source());
+        let client = s3.client();
+
+        let multipart = server_side_copy(
+            &client,
+            &client,
+            "videos",
+            &listed("clip.mp4", 17),
+            "videos",
+            "backup/clip.mp4",
+            &UploadOptions {
+                acl: Some(ObjectCannedAcl::BucketOwnerFullControl),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(!multipart);
+        let copied = s3.stored_object("videos", "backup/clip.mp4").unwrap();
+        assert_eq!(copied.content, source().content);
+        assert_eq!(copied.headers, source().headers);
+        assert_eq!(copied.tagging, source().tagging);
+        assert_eq!(copied.acl.as_deref(), Some("bucket-owner-full-control"));
+        assert!(!s3
+            .requests()
+            .iter()
+            .any(|request| request.contains("uploads")));
+    }
+
+    #[tokio::test]
+    async fn objects_over_the_copy_object_limit_are_copied_in_ranges() {
+        let s3 = FakeS3::default().with_stored_object("videos", "clip.mp4", source());
+        let client = s3.client();
+        // Listed as just over 5GiB, and copied in 3GiB parts. The endpoint clamps the ranges to
+        // the small content, which all ends up in the first part.
+        let size = MAX_SINGLEPART_SIZE as i64 + 1;
+
+        let multipart = server_side_copy(
+            &client,
+            &client,
+            "videos",
+            &listed("clip.mp4", size),
+            "videos",
+            "backup/clip.mp4",
+            &UploadOptions {
+                part_size: 3 << 30,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(multipart);
+        let mut ranges = s3.copied_ranges();
+        ranges.sort();
+        assert_eq!(
+            ranges,
+            [
+                format!("bytes=0-{}", (3_i64 << 30) - 1),
+                format!("bytes={}-{}", 3_i64 << 30, size - 1),
+            ]
+        );
+        let requests = s3.requests();
+        for request in [
+            "POST /videos/backup/clip.mp4?uploads",
+            "PUT /videos/backup/clip.mp4?x-id=UploadPartCopy&partNumber=1&uploadId=upload-1",
+            "PUT /videos/backup/clip.mp4?x-id=UploadPartCopy&partNumber=2&uploadId=upload-1",
+            "POST /videos/backup/clip.mp4?uploadId=upload-1",
+        ] {
+            assert!(
+                requests.iter().any(|sent| sent.starts_with(request)),
+                "{} not in {:?}",
+                request,
+                requests
+            );
+        }
+        assert_eq!(s3.pending_uploads(), 0);
+        // UploadPartCopy copies none of the headers or tags, so they are read from the source.
+        let copied = s3.stored_object("videos", "backup/clip.mp4").unwrap();
+        assert_eq!(copied.content, source().content);
+        assert_eq!(copied.headers, source().headers);
+        assert_eq!(copied.tagging, source().tagging);
+    }
+}
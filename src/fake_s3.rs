@@ -0,0 +1,694 @@
+//! An in-memory S3 endpoint for tests, answering the SDK through its test HTTP client. It knows
+//! the requests of a plain run: listing buckets and objects, bucket creation and location,
+//! reading, writing and copying objects with their headers and tags, and multipart uploads,
+//! including parts copied from another object.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use aws_sdk_s3::{
+    config::{retry::RetryConfig, BehaviorVersion, Credentials, Region},
+    primitives::SdkBody,
+    Client, Config,
+};
+use aws_smithy_runtime::client::http::test_util::infallible_client_fn;
+use http::{Method, Request, Response};
+use percent_encoding::percent_decode_str;
+
+const LAST_MODIFIED: &str = "2024-01-01T00:00:00.000Z";
+/// Most keys returned by one listing request.
+const LIST_PAGE_SIZE: usize = 1000;
+/// Canonical user ID listed as the owner of every object.
+pub const OWNER: &str = "owner-id";
+/// Request headers stored with an object and returned when it is read.
+const OBJECT_HEADERS: [&str; 5] = [
+    "content-type",
+    "content-encoding",
+    "cache-control",
+    "content-disposition",
+    "expires",
+];
+
+#[derive(Clone, Default)]
+pub struct StoredObject {
+    pub content: Vec<u8>,
+    /// Content headers and `x-amz-meta-` user metadata, by lower-case name.
+    pub headers: BTreeMap<String, String>,
+    /// Tags as a `Tagging` value (`key=value&...`).
+    pub tagging: Option<String>,
+    /// Canned ACL the object was written with.
+    pub acl: Option<String>,
+}
+
+/// A multipart upload in progress, with its parts by number.
+struct Upload {
+    bucket: String,
+    key: String,
+    object: StoredObject,
+    parts: BTreeMap<i32, Vec<u8>>,
+}
+
+/// Objects by key, by bucket name.
+type Buckets = BTreeMap<String, BTreeMap<String, StoredObject>>;
+
+/// Failures left for `UploadPart`, by bucket, key and part number.
+type FailingParts = HashMap<(String, String, i32), usize>;
+
+#[derive(Clone, Default)]
+pub struct FakeS3 {
+    buckets: Arc<Mutex<Buckets>>,
+    uploads: Arc<Mutex<BTreeMap<String, Upload>>>,
+    /// Multipart uploads ever created, which numbers their IDs.
+    upload_count: Arc<Mutex<usize>>,
+    denied: Arc<Mutex<HashSet<(String, String)>>>,
+    failing_writes: Arc<Mutex<HashSet<(String, String)>>>,
+    failing_parts: Arc<Mutex<FailingParts>>,
+    misreported_sizes: Arc<Mutex<HashSet<(String, String)>>>,
+    no_part_e_tags: Arc<Mutex<bool>>,
+    part_delay: Arc<Mutex<Duration>>,
+    /// Parts being uploaded, and the most there have been at once.
+    parts_in_flight: Arc<Mutex<(usize, usize)>>,
+    regions: Arc<Mutex<BTreeMap<String, String>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+    copied_ranges: Arc<Mutex<Vec<String>>>,
+}
+
+/// A quoted ETag derived from the content, so that equal contents have equal ETags.
+fn e_tag(content: &[u8]) -> String {
+    let hex = content
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("\"{}\"", hex)
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn xml(status: u16, body: String) -> Response<SdkBody> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/xml")
+        .body(SdkBody::from(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}",
+            body
+        )))
+        .unwrap()
+}
+
+/// The headers of the request that are stored with the object.
+fn object_headers(request: &Request<SdkBody>) -> BTreeMap<String, String> {
+    request
+        .headers()
+        .iter()
+        .filter(|(name, _)| {
+            OBJECT_HEADERS.contains(&name.as_str()) || name.as_str().starts_with("x-amz-meta-")
+        })
+        .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_string()))
+        .collect()
+}
+
+fn header(request: &Request<SdkBody>, name: &str) -> Option<String> {
+    request
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap().to_string())
+}
+
+fn error(status: u16, code: &str) -> Response<SdkBody> {
+    xml(
+        status,
+        format!(
+            "<Error><Code>{}</Code><Message>{}</Message></Error>",
+            code, code
+        ),
+    )
+}
+
+impl FakeS3 {
+    pub fn with_object(self, bucket: &str, key: &str, content: &[u8]) -> Self {
+        self.with_stored_object(
+            bucket,
+            key,
+            StoredObject {
+                content: content.to_vec(),
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn with_stored_object(self, bucket: &str, key: &str, object: StoredObject) -> Self {
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(bucket.to_string())
+            .or_default()
+            .insert(key.to_string(), object);
+        self
+    }
+
+    /// Puts the bucket in another region than the client's `us-east-1`. Requests for its objects
+    /// signed for any other region fail with `AuthorizationHeaderMalformed`, like S3's.
+    pub fn with_bucket_region(self, bucket: &str, region: &str) -> Self {
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(bucket.to_string())
+            .or_default();
+        self.regions
+            .lock()
+            .unwrap()
+            .insert(bucket.to_string(), region.to_string());
+        self
+    }
+
+    /// Makes reading the object fail with `AccessDenied`.
+    pub fn deny(self, bucket: &str, key: &str) -> Self {
+        self.denied
+            .lock()
+            .unwrap()
+            .insert((bucket.to_string(), key.to_string()));
+        self
+    }
+
+    /// Makes the next write of the object fail with `InternalError`, once.
+    pub fn fail_next_write(self, bucket: &str, key: &str) -> Self {
+        self.failing_writes
+            .lock()
+            .unwrap()
+            .insert((bucket.to_string(), key.to_string()));
+        self
+    }
+
+    /// Makes `HeadObject` report the object one byte shorter than it is, like an object whose
+    /// body ended early.
+    pub fn misreport_size(self, bucket: &str, key: &str) -> Self {
+        self.misreported_sizes
+            .lock()
+            .unwrap()
+            .insert((bucket.to_string(), key.to_string()));
+        self
+    }
+
+    /// Makes the next `times` uploads of the part of the object fail with `InternalError`.
+    pub fn fail_part(self, bucket: &str, key: &str, part_number: i32, times: usize) -> Self {
+        self.failing_parts
+            .lock()
+            .unwrap()
+            .insert((bucket.to_string(), key.to_string(), part_number), times);
+        self
+    }
+
+    /// Makes `UploadPart` answer without an ETag, like some S3-compatible stores.
+    pub fn without_part_e_tags(self) -> Self {
+        *self.no_part_e_tags.lock().unwrap() = true;
+        self
+    }
+
+    /// Makes each `UploadPart` take this long, blocking the thread that sends it.
+    pub fn with_part_delay(self, delay: Duration) -> Self {
+        *self.part_delay.lock().unwrap() = delay;
+        self
+    }
+
+    /// The most parts that were being uploaded at once.
+    pub fn peak_parts_in_flight(&self) -> usize {
+        self.parts_in_flight.lock().unwrap().1
+    }
+
+    pub fn object(&self, bucket: &str, key: &str) -> Option<Vec<u8>> {
+        Some(self.stored_object(bucket, key)?.content)
+    }
+
+    pub fn stored_object(&self, bucket: &str, key: &str) -> Option<StoredObject> {
+        self.buckets.lock().unwrap().get(bucket)?.get(key).cloned()
+    }
+
+    /// Multipart uploads neither completed nor aborted.
+    pub fn pending_uploads(&self) -> usize {
+        self.uploads.lock().unwrap().len()
+    }
+
+    /// Every request received so far, as `METHOD /path`, followed by `?query` when it has one.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The `x-amz-copy-source-range` of every `UploadPartCopy` received so far.
+    pub fn copied_ranges(&self) -> Vec<String> {
+        self.copied_ranges.lock().unwrap().clone()
+    }
+
+    /// A client that sends each request once, so that every error reaches the copy.
+    pub fn client(&self) -> Client {
+        self.client_with_retries(RetryConfig::disabled())
+    }
+
+    /// A client that retries failed requests twice, right away, like the SDK's default retries.
+    pub fn retrying_client(&self) -> Client {
+        self.client_with_retries(
+            RetryConfig::standard()
+                .with_max_attempts(3)
+                .with_initial_backoff(Duration::from_millis(1)),
+        )
+    }
+
+    fn client_with_retries(&self, retry_config: RetryConfig) -> Client {
+        let fake = self.clone();
+        let config = Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url("http://s3.test")
+            .force_path_style(true)
+            .retry_config(retry_config)
+            .http_client(infallible_client_fn(move |request| fake.respond(request)))
+            .build();
+        Client::from_conf(config)
+    }
+
+    fn respond(&self, request: Request<SdkBody>) -> Response<SdkBody> {
+        let path = percent_decode_str(request.uri().path())
+            .decode_utf8()
+            .unwrap()
+            .to_string();
+        let query = request.uri().query().unwrap_or_default().to_string();
+        self.requests
+            .lock()
+            .unwrap()
+            .push(match request.uri().query() {
+                Some(query) => format!("{} {}?{}", request.method(), path, query),
+                None => format!("{} {}", request.method(), path),
+            });
+        let parameter = |name: &str| {
+            query.split('&').find_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (key == name).then(|| percent_decode_str(value).decode_utf8().unwrap().to_string())
+            })
+        };
+
+        let path = path.trim_start_matches('/');
+        let (bucket, key) = match path.split_once('/') {
+            Some((bucket, key)) if !key.is_empty() => (bucket, Some(key)),
+            Some((bucket, _)) => (bucket, None),
+            None => (path, None),
+        };
+        if let Some(region) = self.regions.lock().unwrap().get(bucket) {
+            // The credential scope is `<key>/<date>/<region>/s3/aws4_request`.
+            let signed_region = header(&request, "authorization").and_then(|authorization| {
+                let scope = authorization.split("Credential=").nth(1)?;
+                Some(scope.split('/').nth(2)?.to_string())
+            });
+            if key.is_some() && signed_region.as_ref() != Some(region) {
+                return error(400, "AuthorizationHeaderMalformed");
+            }
+        }
+        if request.method() == Method::PUT
+            && key.is_some_and(|key| {
+                self.failing_writes
+                    .lock()
+                    .unwrap()
+                    .remove(&(bucket.to_string(), key.to_string()))
+            })
+        {
+            return error(500, "InternalError");
+        }
+        if let (Some(key), Some(part_number)) = (key, parameter("partNumber")) {
+            let part = (
+                bucket.to_string(),
+                key.to_string(),
+                part_number.parse().unwrap(),
+            );
+            if let Some(failures) = self.failing_parts.lock().unwrap().get_mut(&part) {
+                if *failures > 0 {
+                    *failures -= 1;
+                    return error(500, "InternalError");
+                }
+            }
+        }
+        let delay = *self.part_delay.lock().unwrap();
+        if request.method() == Method::PUT && parameter("uploadId").is_some() && !delay.is_zero() {
+            {
+                let mut in_flight = self.parts_in_flight.lock().unwrap();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            thread::sleep(delay);
+            self.parts_in_flight.lock().unwrap().0 -= 1;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        match (request.method().clone(), key) {
+            (Method::GET, None) if bucket.is_empty() => {
+                let names = buckets
+                    .keys()
+                    .map(|name| {
+                        format!(
+                            "<Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket>",
+                            xml_escape(name),
+                            LAST_MODIFIED
+                        )
+                    })
+                    .collect::<String>();
+                xml(
+                    200,
+                    format!(
+                        "<ListAllMyBucketsResult><Buckets>{}</Buckets></ListAllMyBucketsResult>",
+                        names
+                    ),
+                )
+            }
+            (Method::PUT, None) => {
+                if buckets.contains_key(bucket) {
+                    return error(409, "BucketAlreadyOwnedByYou");
+                }
+                buckets.insert(bucket.to_string(), BTreeMap::new());
+                Response::builder()
+                    .status(200)
+                    .body(SdkBody::empty())
+                    .unwrap()
+            }
+            (Method::GET, None) => {
+                let Some(objects) = buckets.get(bucket) else {
+                    return error(404, "NoSuchBucket");
+                };
+                if parameter("location").is_some() {
+                    return xml(
+                        200,
+                        match self.regions.lock().unwrap().get(bucket) {
+                            Some(region) => {
+                                format!("<LocationConstraint>{}</LocationConstraint>", region)
+                            }
+                            None => "<LocationConstraint/>".to_string(),
+                        },
+                    );
+                }
+                // Pages of 1000 keys like S3's. Version 1 continues after `marker`, version 2
+                // after `continuation-token`, which is the last key of the previous page here.
+                let prefix = parameter("prefix").unwrap_or_default();
+                let after = parameter("marker")
+                    .or_else(|| parameter("continuation-token"))
+                    .or_else(|| parameter("start-after"))
+                    .unwrap_or_default();
+                let mut listed = objects
+                    .iter()
+                    .filter(|(key, _)| key.starts_with(&prefix) && key.as_str() > after.as_str());
+                let page = listed.by_ref().take(LIST_PAGE_SIZE).collect::<Vec<_>>();
+                let truncated = listed.next().is_some();
+                let contents = page
+                    .iter()
+                    .map(|(key, StoredObject { content, .. })| {
+                        format!(
+                            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass><Owner><ID>{}</ID></Owner></Contents>",
+                            xml_escape(key),
+                            LAST_MODIFIED,
+                            e_tag(content),
+                            content.len(),
+                            OWNER
+                        )
+                    })
+                    .collect::<String>();
+                let next_token = match page.last() {
+                    Some((key, _)) if truncated && parameter("list-type").is_some() => format!(
+                        "<NextContinuationToken>{}</NextContinuationToken>",
+                        xml_escape(key)
+                    ),
+                    _ => String::new(),
+                };
+                xml(
+                    200,
+                    format!(
+                        "<ListBucketResult><Name>{}</Name><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+                        xml_escape(bucket),
+                        truncated,
+                        next_token,
+                        contents
+                    ),
+                )
+            }
+            (Method::GET | Method::HEAD, Some(key)) => {
+                if self
+                    .denied
+                    .lock()
+                    .unwrap()
+                    .contains(&(bucket.to_string(), key.to_string()))
+                {
+                    return error(403, "AccessDenied");
+                }
+                let Some(object) = buckets.get(bucket).and_then(|objects| objects.get(key)) else {
+                    return error(404, "NoSuchKey");
+                };
+                let tags = object
+                    .tagging
+                    .iter()
+                    .flat_map(|tagging| tagging.split('&'))
+                    .map(|tag| {
+                        let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                        let decode = |value| percent_decode_str(value).decode_utf8().unwrap();
+                        (decode(key), decode(value))
+                    })
+                    .collect::<Vec<_>>();
+                if parameter("tagging").is_some() {
+                    let tag_set = tags
+                        .iter()
+                        .map(|(key, value)| {
+                            format!(
+                                "<Tag><Key>{}</Key><Value>{}</Value></Tag>",
+                                xml_escape(key),
+                                xml_escape(value)
+                            )
+                        })
+                        .collect::<String>();
+                    return xml(
+                        200,
+                        format!("<Tagging><TagSet>{}</TagSet></Tagging>", tag_set),
+                    );
+                }
+                // No object has Object Lock retention or a legal hold.
+                if parameter("retention").is_some() || parameter("legal-hold").is_some() {
+                    return error(404, "NoSuchObjectLockConfiguration");
+                }
+                let (body, content_length) = if request.method() == Method::HEAD {
+                    let misreported = self
+                        .misreported_sizes
+                        .lock()
+                        .unwrap()
+                        .contains(&(bucket.to_string(), key.to_string()));
+                    (
+                        SdkBody::empty(),
+                        object.content.len().saturating_sub(misreported as usize),
+                    )
+                } else {
+                    (SdkBody::from(object.content.clone()), object.content.len())
+                };
+                let mut response = Response::builder()
+                    .status(200)
+                    .header("content-length", content_length)
+                    .header("etag", e_tag(&object.content))
+                    .header("last-modified", "Mon, 01 Jan 2024 00:00:00 GMT");
+                for (name, value) in &object.headers {
+                    response = response.header(name, value);
+                }
+                if !tags.is_empty() {
+                    response = response.header("x-amz-tagging-count", tags.len());
+                }
+                response.body(body).unwrap()
+            }
+            (Method::PUT, Some(_)) if parameter("uploadId").is_some() => {
+                let mut uploads = self.uploads.lock().unwrap();
+                let Some(upload) = uploads.get_mut(&parameter("uploadId").unwrap()) else {
+                    return error(404, "NoSuchUpload");
+                };
+                let copied = header(&request, "x-amz-copy-source");
+                let content = match &copied {
+                    Some(source) => {
+                        let source = percent_decode_str(source)
+                            .decode_utf8()
+                            .unwrap()
+                            .to_string();
+                        let (source_bucket, source_key) =
+                            source.trim_start_matches('/').split_once('/').unwrap();
+                        let Some(source) = buckets
+                            .get(source_bucket)
+                            .and_then(|objects| objects.get(source_key))
+                        else {
+                            return error(404, "NoSuchKey");
+                        };
+                        // `bytes=<first>-<last>`, clamped to the content so that a small object
+                        // can stand in for one listed as larger.
+                        let range = header(&request, "x-amz-copy-source-range").unwrap();
+                        let (first, last) =
+                            range.trim_start_matches("bytes=").split_once('-').unwrap();
+                        let length = source.content.len();
+                        let first = first.parse::<usize>().unwrap().min(length);
+                        let end = (last.parse::<usize>().unwrap() + 1).min(length);
+                        self.copied_ranges.lock().unwrap().push(range);
+                        source.content[first..end].to_vec()
+                    }
+                    None => request.body().bytes().unwrap().to_vec(),
+                };
+                let response_e_tag = e_tag(&content);
+                upload
+                    .parts
+                    .insert(parameter("partNumber").unwrap().parse().unwrap(), content);
+                if copied.is_some() {
+                    return xml(
+                        200,
+                        format!(
+                            "<CopyPartResult><ETag>{}</ETag><LastModified>{}</LastModified></CopyPartResult>",
+                            response_e_tag, LAST_MODIFIED
+                        ),
+                    );
+                }
+                let mut response = Response::builder().status(200);
+                if !*self.no_part_e_tags.lock().unwrap() {
+                    response = response.header("etag", response_e_tag);
+                }
+                response.body(SdkBody::empty()).unwrap()
+            }
+            (Method::PUT, Some(key)) => {
+                let object = match header(&request, "x-amz-copy-source") {
+                    Some(source) => {
+                        let source = percent_decode_str(&source)
+                            .decode_utf8()
+                            .unwrap()
+                            .to_string();
+                        let (source_bucket, source_key) =
+                            source.trim_start_matches('/').split_once('/').unwrap();
+                        let Some(source) = buckets
+                            .get(source_bucket)
+                            .and_then(|objects| objects.get(source_key))
+                        else {
+                            return error(404, "NoSuchKey");
+                        };
+                        let replace = |directive| {
+                            header(&request, directive).is_some_and(|value| value == "REPLACE")
+                        };
+                        StoredObject {
+                            content: source.content.clone(),
+                            headers: if replace("x-amz-metadata-directive") {
+                                object_headers(&request)
+                            } else {
+                                source.headers.clone()
+                            },
+                            tagging: if replace("x-amz-tagging-directive") {
+                                header(&request, "x-amz-tagging")
+                            } else {
+                                source.tagging.clone()
+                            },
+                            acl: header(&request, "x-amz-acl"),
+                        }
+                    }
+                    None => StoredObject {
+                        content: request.body().bytes().unwrap().to_vec(),
+                        headers: object_headers(&request),
+                        tagging: header(&request, "x-amz-tagging"),
+                        acl: header(&request, "x-amz-acl"),
+                    },
+                };
+                let Some(objects) = buckets.get_mut(bucket) else {
+                    return error(404, "NoSuchBucket");
+                };
+                if header(&request, "if-none-match").is_some() && objects.contains_key(key) {
+                    return error(412, "PreconditionFailed");
+                }
+                let response_e_tag = e_tag(&object.content);
+                let copied = request.headers().contains_key("x-amz-copy-source");
+                objects.insert(key.to_string(), object);
+                if copied {
+                    return xml(
+                        200,
+                        format!(
+                            "<CopyObjectResult><ETag>{}</ETag><LastModified>{}</LastModified></CopyObjectResult>",
+                            response_e_tag, LAST_MODIFIED
+                        ),
+                    );
+                }
+                Response::builder()
+                    .status(200)
+                    .header("etag", response_e_tag)
+                    .body(SdkBody::empty())
+                    .unwrap()
+            }
+            (Method::POST, Some(key)) if parameter("uploads").is_some() => {
+                if !buckets.contains_key(bucket) {
+                    return error(404, "NoSuchBucket");
+                }
+                let mut uploads = self.uploads.lock().unwrap();
+                let upload_id = {
+                    let mut upload_count = self.upload_count.lock().unwrap();
+                    *upload_count += 1;
+                    format!("upload-{}", upload_count)
+                };
+                uploads.insert(
+                    upload_id.clone(),
+                    Upload {
+                        bucket: bucket.to_string(),
+                        key: key.to_string(),
+                        object: StoredObject {
+                            content: vec![],
+                            headers: object_headers(&request),
+                            tagging: header(&request, "x-amz-tagging"),
+                            acl: header(&request, "x-amz-acl"),
+                        },
+                        parts: BTreeMap::new(),
+                    },
+                );
+                xml(
+                    200,
+                    format!(
+                        "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                        xml_escape(bucket),
+                        xml_escape(key),
+                        upload_id
+                    ),
+                )
+            }
+            (Method::POST, Some(key)) if parameter("uploadId").is_some() => {
+                let mut uploads = self.uploads.lock().unwrap();
+                let upload_id = parameter("uploadId").unwrap();
+                let objects = buckets.get_mut(bucket).unwrap();
+                if header(&request, "if-none-match").is_some() && objects.contains_key(key) {
+                    return error(412, "PreconditionFailed");
+                }
+                let Some(upload) = uploads.remove(&upload_id) else {
+                    return error(404, "NoSuchUpload");
+                };
+                assert_eq!((upload.bucket.as_str(), upload.key.as_str()), (bucket, key));
+                let object = StoredObject {
+                    content: upload.parts.into_values().flatten().collect(),
+                    ..upload.object
+                };
+                let response_e_tag = e_tag(&object.content);
+                objects.insert(key.to_string(), object);
+                xml(
+                    200,
+                    format!(
+                        "<CompleteMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+                        xml_escape(bucket),
+                        xml_escape(key),
+                        response_e_tag
+                    ),
+                )
+            }
+            (Method::DELETE, Some(_)) if parameter("uploadId").is_some() => {
+                self.uploads
+                    .lock()
+                    .unwrap()
+                    .remove(&parameter("uploadId").unwrap());
+                Response::builder()
+                    .status(204)
+                    .body(SdkBody::empty())
+                    .unwrap()
+            }
+            _ => error(501, "NotImplemented"),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use aws_sdk_s3::types::Object;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Datelike, Timelike, Utc,
+};
+use tracing::warn;
+
+/// A kind of object key that tends to break downstream systems.
+#[derive(Clone, Copy)]
+pub enum KeyCheck {
+    Control,
+    Whitespace,
+    DoubleSlash,
+    DotSegment,
+}
+
+impl KeyCheck {
+    pub const ALL: [KeyCheck; 4] = [
+        KeyCheck::Control,
+        KeyCheck::Whitespace,
+        KeyCheck::DoubleSlash,
+        KeyCheck::DotSegment,
+    ];
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "control" => KeyCheck::Control,
+            "whitespace" => KeyCheck::Whitespace,
+            "double-slash" => KeyCheck::DoubleSlash,
+            "dot-segment" => KeyCheck::DotSegment,
+            _ => panic!("Invalid key check: {}", name),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyCheck::Control => "control",
+            KeyCheck::Whitespace => "whitespace",
+            KeyCheck::DoubleSlash => "double-slash",
+            KeyCheck::DotSegment => "dot-segment",
+        }
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            KeyCheck::Control => key.chars().any(char::is_control),
+            KeyCheck::Whitespace => key.trim() != key,
+            KeyCheck::DoubleSlash => key.contains("//"),
+            KeyCheck::DotSegment => key
+                .split('/')
+                .any(|segment| segment == "." || segment == ".."),
+        }
+    }
+
+    fn sanitize(&self, key: &str) -> String {
+        match self {
+            KeyCheck::Control => key.chars().filter(|c| !c.is_control()).collect(),
+            KeyCheck::Whitespace => key.trim().to_string(),
+            KeyCheck::DoubleSlash => {
+                let mut sanitized = key.to_string();
+                while sanitized.contains("//") {
+                    sanitized = sanitized.replace("//", "/");
+                }
+                sanitized
+            }
+            KeyCheck::DotSegment => key
+                .split('/')
+                .filter(|segment| *segment != "." && *segment != "..")
+                .collect::<Vec<&str>>()
+                .join("/"),
+        }
+    }
+}
+
+enum TemplatePart {
+    Literal(String),
+    Bucket,
+    Key,
+    Basename,
+    Year,
+    Month,
+    Day,
+    Hour,
+}
+
+impl TemplatePart {
+    fn uses_date(&self) -> bool {
+        matches!(
+            self,
+            TemplatePart::Year | TemplatePart::Month | TemplatePart::Day | TemplatePart::Hour
+        )
+    }
+}
+
+fn parse_template(template: &str) -> Vec<TemplatePart> {
+    let mut parts = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if rest[..start].contains('}') {
+            panic!("Unopened placeholder in KEY_TEMPLATE: {}", template);
+        }
+        if start > 0 {
+            parts.push(TemplatePart::Literal(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .unwrap_or_else(|| panic!("Unclosed placeholder in KEY_TEMPLATE: {}", template));
+        parts.push(match &rest[start + 1..start + end] {
+            "bucket" => TemplatePart::Bucket,
+            "key" => TemplatePart::Key,
+            "basename" => TemplatePart::Basename,
+            "year" => TemplatePart::Year,
+            "month" => TemplatePart::Month,
+            "day" => TemplatePart::Day,
+            "hour" => TemplatePart::Hour,
+            placeholder => panic!("Unknown placeholder in KEY_TEMPLATE: {{{}}}", placeholder),
+        });
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        panic!("Unopened placeholder in KEY_TEMPLATE: {}", template);
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Literal(rest.to_string()));
+    }
+    parts
+}
+
+/// Rules that turn a source object into its key in the destination bucket. The default keeps
+/// every key.
+#[derive(Default)]
+pub struct KeyRules {
+    date_partition: Option<String>,
+    template: Option<Vec<TemplatePart>>,
+    sanitize: Vec<KeyCheck>,
+}
+
+impl KeyRules {
+    pub fn new(
+        date_partition: Option<String>,
+        template: Option<&str>,
+        sanitize: Vec<KeyCheck>,
+    ) -> Self {
+        if let Some(format) = &date_partition {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                panic!("Invalid DATE_PARTITION format: {}", format);
+            }
+        }
+        KeyRules {
+            date_partition,
+            template: template.map(parse_template),
+            sanitize,
+        }
+    }
+
+    /// Whether every object keeps its source key.
+    pub fn is_identity(&self) -> bool {
+        self.date_partition.is_none() && self.template.is_none() && self.sanitize.is_empty()
+    }
+
+    fn uses_date(&self) -> bool {
+        self.date_partition.is_some()
+            || self
+                .template
+                .as_ref()
+                .is_some_and(|template| template.iter().any(TemplatePart::uses_date))
+    }
+
+    pub fn destination_key(&self, bucket: &str, object: &Object) -> String {
+        let mut destination_key = self.transform(bucket, object);
+        for check in &self.sanitize {
+            destination_key = check.sanitize(&destination_key);
+        }
+        destination_key
+    }
+
+    fn transform(&self, bucket: &str, object: &Object) -> String {
+        let key = object.key.as_deref().unwrap();
+        if self.date_partition.is_none() && self.template.is_none() {
+            return key.to_string();
+        }
+
+        let date = object
+            .last_modified
+            .and_then(|date| DateTime::<Utc>::from_timestamp(date.secs(), date.subsec_nanos()));
+        if date.is_none() && self.uses_date() {
+            warn!(
+                "{} has no last modified date, copying with the original key",
+                key
+            );
+            return key.to_string();
+        }
+
+        let mut destination_key = self
+            .date_partition
+            .as_ref()
+            .zip(date)
+            .map(|(format, date)| date.format(format).to_string())
+            .unwrap_or_default();
+        match &self.template {
+            Some(template) => {
+                for part in template {
+                    match part {
+                        TemplatePart::Literal(literal) => destination_key += literal,
+                        TemplatePart::Bucket => destination_key += bucket,
+                        TemplatePart::Key => destination_key += key,
+                        TemplatePart::Basename => {
+                            destination_key += key.rsplit('/').next().unwrap_or(key)
+                        }
+                        TemplatePart::Year => {
+                            destination_key += &format!("{:04}", date.unwrap().year())
+                        }
+                        TemplatePart::Month => {
+                            destination_key += &format!("{:02}", date.unwrap().month())
+                        }
+                        TemplatePart::Day => {
+                            destination_key += &format!("{:02}", date.unwrap().day())
+                        }
+                        TemplatePart::Hour => {
+                            destination_key += &format!("{:02}", date.unwrap().hour())
+                        }
+                    }
+                }
+            }
+            None => destination_key += key,
+        }
+        destination_key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_s3::primitives::DateTime as SdkDateTime;
+
+    use super::*;
+
+    fn object(key: &str) -> Object {
+        Object::builder()
+            .key(key)
+            // 2024-03-05T07:00:00Z
+            .last_modified(SdkDateTime::from_secs(1709622000))
+            .build()
+    }
+
+    #[test]
+    fn template_fills_every_placeholder() {
+        let rules = KeyRules::new(
+            None,
+            Some("archive/{bucket}/{year}/{month}/{day}/{hour}/{basename}"),
+            vec![],
+        );
+        assert_eq!(
+            rules.destination_key("logs", &object("app/2024/app.log")),
+            "archive/logs/2024/03/05/07/app.log"
+        );
+        let rules = KeyRules::new(None, Some("{key}.bak"), vec![]);
+        assert_eq!(rules.destination_key("logs", &object("a/b")), "a/b.bak");
+    }
+
+    #[test]
+    fn date_partition_is_prefixed_to_the_key() {
+        let rules = KeyRules::new(Some("%Y/%m/".to_string()), None, vec![]);
+        assert_eq!(
+            rules.destination_key("logs", &object("a.log")),
+            "2024/03/a.log"
+        );
+    }
+
+    #[test]
+    fn objects_without_a_date_keep_their_key() {
+        let rules = KeyRules::new(None, Some("{year}/{key}"), vec![]);
+        let object = Object::builder().key("a.log").build();
+        assert_eq!(rules.destination_key("logs", &object), "a.log");
+        let rules = KeyRules::new(None, Some("{bucket}/{key}"), vec![]);
+        assert_eq!(rules.destination_key("logs", &object), "logs/a.log");
+    }
+
+    #[test]
+    fn default_rules_keep_every_key() {
+        let rules = KeyRules::default();
+        assert!(rules.is_identity());
+        assert_eq!(rules.destination_key("logs", &object("a//b")), "a//b");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown placeholder in KEY_TEMPLATE: {date}")]
+    fn unknown_placeholders_are_rejected() {
+        parse_template("{date}/{key}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unclosed placeholder")]
+    fn unclosed_placeholders_are_rejected() {
+        parse_template("{bucket}/{key");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unopened placeholder")]
+    fn unopened_placeholders_are_rejected() {
+        parse_template("bucket}/{key}");
+    }
+}
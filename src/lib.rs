@@ -0,0 +1,122 @@
+//! Copies every bucket of one S3-compatible endpoint to another. [`run::run`] copies the
+//! buckets with the given clients and [`run::RunOptions`], and [`run::copy_bucket`] copies a
+//! single bucket; the `s3copy` binary only reads the options from its arguments and environment
+//! variables and prints the summary. The pieces the run is built from are exposed too, so that
+//! single objects can also be copied programmatically:
+//!
+//! ```no_run
+//! use s3copy::{
+//!     client::{
+//!         credentials_file, get_client, region_from_str, ClientSettings, NEW_CREDENTIALS_FILE,
+//!         OLD_CREDENTIALS_FILE,
+//!     },
+//!     upload::{singlepart_upload, UploadOptions},
+//! };
+//!
+//! # async fn example() -> Result<(), String> {
+//! let old_client = get_client(
+//!     credentials_file(OLD_CREDENTIALS_FILE),
+//!     region_from_str("us-east-1"),
+//!     Some("https://old.example.com"),
+//!     &ClientSettings::default(),
+//! )
+//! .await;
+//! let new_client = get_client(
+//!     credentials_file(NEW_CREDENTIALS_FILE),
+//!     region_from_str("us-east-1"),
+//!     Some("https://new.example.com"),
+//!     &ClientSettings::default(),
+//! )
+//! .await;
+//!
+//! let object = old_client
+//!     .get_object()
+//!     .bucket("photos")
+//!     .key("2024/cat.jpg")
+//!     .send()
+//!     .await
+//!     .map_err(|e| e.to_string())?;
+//! singlepart_upload(
+//!     &new_client,
+//!     "photos",
+//!     "2024/cat.jpg",
+//!     object,
+//!     None,
+//!     None,
+//!     &UploadOptions::default(),
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::env;
+
+pub mod checksum;
+pub mod client;
+pub mod config;
+pub mod copy;
+#[cfg(test)]
+mod fake_s3;
+pub mod key;
+pub mod listing;
+pub mod manifest;
+pub mod notification;
+pub mod plan;
+#[cfg(feature = "presigned")]
+pub mod presigned;
+pub mod progress;
+pub mod retention;
+pub mod run;
+pub mod state;
+pub mod upload;
+pub mod watermark;
+
+/// Parses a size such as `512`, `16MiB` or `1GB`. Units are binary whatever their spelling.
+pub fn parse_size(size: &str) -> Option<i64> {
+    let size = size.trim();
+    let unit_start = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let multiplier: i64 = match size[unit_start..].trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+    size[..unit_start]
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(multiplier)
+}
+
+/// Whether the variable is set to `1` or `true`.
+pub fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| value == "1" || value == "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_accept_binary_units_and_raw_bytes() {
+        assert_eq!(parse_size("64MB"), Some(64 << 20));
+        assert_eq!(parse_size("5242880"), Some(5 * 1024 * 1024));
+        assert_eq!(parse_size(" 16 MiB "), Some(16 << 20));
+        assert_eq!(parse_size("1gib"), Some(1 << 30));
+        assert_eq!(parse_size("2T"), Some(2 << 40));
+        assert_eq!(parse_size("512B"), Some(512));
+    }
+
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        assert_eq!(parse_size("64XB"), None);
+        assert_eq!(parse_size("MB"), None);
+        assert_eq!(parse_size("-1MB"), None);
+        assert_eq!(parse_size("1.5GB"), None);
+        assert_eq!(parse_size("99999999999TB"), None);
+    }
+}
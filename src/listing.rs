@@ -0,0 +1,373 @@
+use std::{fmt::Debug, future::Future, sync::Mutex, time::Duration};
+
+use aws_sdk_s3::{
+    types::{Object, RequestPayer},
+    Client,
+};
+use futures::stream::{self, StreamExt};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+const LISTING_PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Retries of a failed listing page on top of the SDK's own, which give up on errors that last
+/// more than a few seconds.
+pub struct ListingRetries {
+    attempts: u32,
+    skip_unreadable: bool,
+    unlisted: Mutex<Vec<String>>,
+}
+
+impl ListingRetries {
+    pub fn new(attempts: u32, skip_unreadable: bool) -> Self {
+        ListingRetries {
+            attempts,
+            skip_unreadable,
+            unlisted: Mutex::new(vec![]),
+        }
+    }
+
+    /// Sends the page request until it succeeds, waiting twice as long after each failure. When
+    /// every attempt fails, `range` (the keys the page and the rest of the listing would have
+    /// covered) is recorded and `None` returned if unreadable pages are skipped; otherwise the
+    /// error is returned, and the bucket is given up on.
+    async fn page<T, E: Debug, F: Future<Output = Result<T, E>>>(
+        &self,
+        range: String,
+        send: impl Fn() -> F,
+    ) -> Result<Option<T>, String> {
+        let mut delay = Duration::from_secs(1);
+        for attempt in 1.. {
+            match send().await {
+                Ok(output) => return Ok(Some(output)),
+                Err(e) if attempt < self.attempts => {
+                    warn!(
+                        "Listing failed (attempt {}/{}), retrying in {}s: {}: {:?}",
+                        attempt,
+                        self.attempts,
+                        delay.as_secs(),
+                        range,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) if self.skip_unreadable => {
+                    error!("Listing given up: {}: {:?}", range, e);
+                    self.unlisted.lock().unwrap().push(range);
+                    return Ok(None);
+                }
+                Err(e) => return Err(format!("{}: {:?}", range, e)),
+            }
+        }
+        unreachable!()
+    }
+
+    pub fn unlisted_count(&self) -> usize {
+        self.unlisted.lock().unwrap().len()
+    }
+
+    /// The key ranges given up on so far.
+    pub fn into_unlisted(self) -> Vec<String> {
+        self.unlisted.into_inner().unwrap()
+    }
+}
+
+/// Logs a listing that is still going once per `interval`, so that a bucket with millions of
+/// keys can be told apart from one that is stuck.
+struct ListingProgress {
+    listing: String,
+    interval: Duration,
+    pages: usize,
+    logged: Instant,
+}
+
+impl ListingProgress {
+    fn new(bucket: &str, prefix: Option<&str>) -> Self {
+        ListingProgress {
+            listing: listing_range(bucket, prefix, None),
+            interval: LISTING_PROGRESS_INTERVAL,
+            pages: 0,
+            logged: Instant::now(),
+        }
+    }
+
+    /// Counts a page that has been read, and returns whether the listing was logged.
+    fn page(&mut self, objects: usize) -> bool {
+        self.pages += 1;
+        if self.logged.elapsed() < self.interval {
+            return false;
+        }
+        info!(
+            "Still listing {} (page {}, {} objects)",
+            self.listing, self.pages, objects
+        );
+        self.logged = Instant::now();
+        true
+    }
+}
+
+/// The keys of a listing from `marker` on, as recorded for a page that could not be read.
+fn listing_range(bucket: &str, prefix: Option<&str>, marker: Option<&str>) -> String {
+    let range = format!("{}/{}*", bucket, prefix.unwrap_or_default());
+    match marker {
+        Some(marker) => format!("{} after {}", range, marker),
+        None => range,
+    }
+}
+
+/// Lists every object under the prefix. `ListObjects` returns at most 1000 keys per call, so
+/// this follows `NextMarker`, or the last key when it is absent (it is only returned when a
+/// delimiter is given). The listing stops at the first page that takes it over `max_objects`.
+pub async fn list_source_objects(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    request_payer: Option<RequestPayer>,
+    retries: &ListingRetries,
+    max_objects: Option<usize>,
+) -> Result<Vec<Object>, String> {
+    let mut objects: Vec<Object> = vec![];
+    let mut marker: Option<String> = None;
+    let mut progress = ListingProgress::new(bucket, prefix);
+    loop {
+        let request = client
+            .list_objects()
+            .set_request_payer(request_payer.clone())
+            .bucket(bucket)
+            .set_prefix(prefix.map(str::to_string))
+            .set_marker(marker.clone());
+        let Some(output) = retries
+            .page(listing_range(bucket, prefix, marker.as_deref()), || {
+                request.clone().send()
+            })
+            .await?
+        else {
+            return Ok(objects);
+        };
+        objects.extend(output.contents.unwrap_or_default());
+        progress.page(objects.len());
+        if !output.is_truncated.unwrap_or_default()
+            || max_objects.is_some_and(|max_objects| objects.len() > max_objects)
+        {
+            return Ok(objects);
+        }
+        marker = output
+            .next_marker
+            .or_else(|| objects.last().and_then(|object| object.key.clone()));
+    }
+}
+
+/// Lists the bucket's top-level `/` prefixes with one delimited listing, then lists the prefixes
+/// `concurrency` at a time. Objects at the root come from the delimited listing itself. The
+/// prefixes do not overlap, so every key is listed once; the result is sorted by key like a
+/// plain listing. Like a plain listing, it stops once it is over `max_objects`.
+pub async fn list_source_objects_sharded(
+    client: &Client,
+    bucket: &str,
+    request_payer: Option<RequestPayer>,
+    concurrency: usize,
+    retries: &ListingRetries,
+    max_objects: Option<usize>,
+) -> Result<Vec<Object>, String> {
+    let mut objects: Vec<Object> = vec![];
+    let mut shards = vec![];
+    let mut marker: Option<String> = None;
+    let mut progress = ListingProgress::new(bucket, None);
+    loop {
+        let request = client
+            .list_objects()
+            .set_request_payer(request_payer.clone())
+            .bucket(bucket)
+            .delimiter("/")
+            .set_marker(marker.clone());
+        let Some(output) = retries
+            .page(listing_range(bucket, None, marker.as_deref()), || {
+                request.clone().send()
+            })
+            .await?
+        else {
+            break;
+        };
+        let page_objects = output.contents.unwrap_or_default();
+        let page_shards = output
+            .common_prefixes
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|prefix| prefix.prefix)
+            .collect::<Vec<String>>();
+        // `NextMarker` is returned with a delimiter, but fall back to the last key or prefix of
+        // the page like a plain listing does.
+        let last = page_objects
+            .last()
+            .and_then(|object| object.key.clone())
+            .max(page_shards.last().cloned());
+        objects.extend(page_objects);
+        shards.extend(page_shards);
+        progress.page(objects.len());
+        if max_objects.is_some_and(|max_objects| objects.len() > max_objects) {
+            return Ok(objects);
+        }
+        if !output.is_truncated.unwrap_or_default() {
+            break;
+        }
+        marker = output.next_marker.or(last);
+    }
+    info!(
+        "Auto shard: {} prefixes, {} objects at the root",
+        shards.len(),
+        objects.len()
+    );
+
+    // Each prefix is capped at what was left when the listings started, and the listings still
+    // running are dropped as soon as the total goes over.
+    let remaining = max_objects.map(|max_objects| max_objects - objects.len());
+    let mut listings = stream::iter(&shards)
+        .map(|prefix| {
+            list_source_objects(
+                client,
+                bucket,
+                Some(prefix),
+                request_payer.clone(),
+                retries,
+                remaining,
+            )
+        })
+        .buffer_unordered(concurrency);
+    while let Some(shard_objects) = listings.next().await {
+        objects.extend(shard_objects?);
+        if max_objects.is_some_and(|max_objects| objects.len() > max_objects) {
+            break;
+        }
+    }
+    objects.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+    Ok(objects)
+}
+
+/// Lists every object under the prefix in the destination bucket, following continuation
+/// tokens.
+pub async fn list_destination_objects(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    request_payer: Option<RequestPayer>,
+    retries: &ListingRetries,
+) -> Result<Vec<Object>, String> {
+    let mut objects: Vec<Object> = vec![];
+    let mut continuation_token = None;
+    let mut progress = ListingProgress::new(bucket, prefix);
+    loop {
+        let request = client
+            .list_objects_v2()
+            .set_request_payer(request_payer.clone())
+            .bucket(bucket)
+            .set_prefix(prefix.map(str::to_string))
+            .set_continuation_token(continuation_token);
+        let range = listing_range(
+            bucket,
+            prefix,
+            objects.last().and_then(|object| object.key.as_deref()),
+        );
+        let output = retries
+            .page(range, || async {
+                match request.clone().send().await {
+                    Ok(output) => Ok(Some(output)),
+                    // Only a dry run gets here before the bucket has been created.
+                    Err(e) if format!("{:?}", e).contains("NoSuchBucket") => Ok(None),
+                    Err(e) => Err(e),
+                }
+            })
+            .await?;
+        let Some(Some(output)) = output else {
+            return Ok(objects);
+        };
+        objects.extend(output.contents.unwrap_or_default());
+        progress.page(objects.len());
+        if !output.is_truncated.unwrap_or_default() {
+            return Ok(objects);
+        }
+        continuation_token = output.next_continuation_token;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_s3::FakeS3;
+
+    fn bucket_of(count: usize) -> FakeS3 {
+        (0..count).fold(FakeS3::default(), |fake, i| {
+            fake.with_object("logs", &format!("{:05}.log", i), b"log")
+        })
+    }
+
+    #[tokio::test]
+    async fn listings_follow_every_page() {
+        let fake = bucket_of(2500);
+        let retries = ListingRetries::new(1, false);
+
+        let source = list_source_objects(&fake.client(), "logs", None, None, &retries, None)
+            .await
+            .unwrap();
+        let destination = list_destination_objects(&fake.client(), "logs", None, None, &retries)
+            .await
+            .unwrap();
+
+        for objects in [source, destination] {
+            let keys = objects
+                .iter()
+                .map(|object| object.key().unwrap().to_string())
+                .collect::<Vec<String>>();
+            assert_eq!(
+                keys,
+                (0..2500)
+                    .map(|i| format!("{:05}.log", i))
+                    .collect::<Vec<String>>()
+            );
+        }
+        assert_eq!(fake.requests().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn long_listings_are_logged_once_per_interval() {
+        let mut progress = ListingProgress::new("logs", Some("2024/"));
+        assert!(!progress.page(1000));
+        progress.interval = Duration::ZERO;
+        assert!(progress.page(2000));
+        assert_eq!(progress.pages, 2);
+        assert_eq!(progress.listing, "logs/2024/*");
+    }
+
+    #[tokio::test]
+    async fn source_listing_stops_once_over_the_cap() {
+        let fake = bucket_of(2500);
+        let retries = ListingRetries::new(1, false);
+
+        let objects = list_source_objects(&fake.client(), "logs", None, None, &retries, Some(1500))
+            .await
+            .unwrap();
+
+        assert_eq!(objects.len(), 2000);
+        assert_eq!(fake.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unreadable_pages_fail_the_listing_unless_skipped() {
+        let fake = bucket_of(1);
+
+        let retries = ListingRetries::new(1, false);
+        let error = list_source_objects(&fake.client(), "missing", None, None, &retries, None)
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("missing/*: "), "{}", error);
+        assert!(error.contains("NoSuchBucket"), "{}", error);
+        assert_eq!(retries.unlisted_count(), 0);
+
+        let retries = ListingRetries::new(1, true);
+        let objects = list_source_objects(&fake.client(), "missing", None, None, &retries, None)
+            .await
+            .unwrap();
+        assert!(objects.is_empty());
+        assert_eq!(retries.into_unlisted(), ["missing/*"]);
+    }
+}
@@ -1,176 +1,467 @@
-use std::env;
-
-use aws_config::Region;
-use aws_runtime::env_config::file::{EnvConfigFileKind, EnvConfigFiles};
-use aws_sdk_s3::{
-    config::Builder,
-    types::{BucketLocationConstraint, CreateBucketConfiguration, Object},
-    Client,
+mod cli;
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg(feature = "tui")]
+mod tui;
+
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    io::{self, IsTerminal, Write},
+    process,
+    sync::Arc,
+    time::Duration,
 };
 
-const MAX_KEYS: i32 = 1000000;
-
-async fn get_client(
-    env_config_files: EnvConfigFiles,
-    region: Region,
-    endpoint_url: Option<&str>,
-) -> Client {
-    let mut config_loader = aws_config::from_env()
-        .profile_files(env_config_files)
-        .region(region);
-    config_loader = match endpoint_url {
-        Some(url) => config_loader.endpoint_url(url),
-        None => config_loader,
+use aws_sdk_s3::types::{ObjectCannedAcl, RequestPayer};
+use clap::Parser;
+use tokio::sync::Notify;
+use tracing::warn;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use s3copy::{
+    client::{credentials_file, get_client, region_from_str},
+    config::Config,
+    copy::same_credentials,
+    env_flag,
+    key::{KeyCheck, KeyRules},
+    notification::read_arn_map,
+    parse_size,
+    plan::PlanFormat,
+    progress::Progress,
+    run::{run, CaseCollisions, FailureCount, KeyValidation, ObjectOrder, RunOptions},
+    upload::{
+        parse_part_size_tiers, parse_storage_class_rules, MemoryBudget, OpenUploads, RequestLimit,
+        StorageClassMap, UploadOptions, MIN_PART_SIZE,
+    },
+};
+
+use crate::cli::Cli;
+
+fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let (value, multiplier) = match duration.char_indices().last()? {
+        (i, 's') => (&duration[..i], 1),
+        (i, 'm') => (&duration[..i], 60),
+        (i, 'h') => (&duration[..i], 60 * 60),
+        _ => (duration, 1),
     };
-    let config = Builder::from(&config_loader.load().await)
-        .force_path_style(true)
-        .build();
-    Client::from_conf(config)
+    Some(Duration::from_secs(value.parse::<u64>().ok()? * multiplier))
 }
 
-fn region_from_str(region: &str) -> Region {
-    match region {
-        "us-east-1" => Region::from_static("us-east-1"),
-        "ap-northeast-1" => Region::from_static("ap-northeast-1"),
-        "ap-northeast-3" => Region::from_static("ap-northeast-3"),
-        _ => panic!("Invalid region"),
+fn read_key_list(path: &str) -> HashSet<String> {
+    fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reads `old<TAB>new` lines renaming user metadata keys. A line with only `old` drops the key.
+/// Keys are compared in lower case, which is how S3 returns them.
+fn read_metadata_key_rewrites(path: &str) -> HashMap<String, Option<String>> {
+    fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('\t') {
+            Some((old, new)) if !new.is_empty() => (old.to_lowercase(), Some(new.to_lowercase())),
+            Some((old, _)) => (old.to_lowercase(), None),
+            None => (line.to_lowercase(), None),
+        })
+        .collect()
+}
+
+/// Sorts the prefixes and drops any prefix already covered by a shorter one.
+fn dedup_prefixes(prefixes: HashSet<String>) -> Vec<String> {
+    let mut prefixes = prefixes.into_iter().collect::<Vec<String>>();
+    prefixes.sort_unstable();
+    let mut deduped: Vec<String> = vec![];
+    for prefix in prefixes {
+        if !deduped.last().is_some_and(|last| prefix.starts_with(last)) {
+            deduped.push(prefix);
+        }
+    }
+    deduped
+}
+
+/// Splits a comma-separated env var into its non-empty entries.
+fn env_list(name: &str) -> Option<Vec<String>> {
+    env::var(name).ok().map(|value| {
+        value
+            .split(',')
+            .filter(|entry| !entry.is_empty())
+            .map(str::to_string)
+            .collect()
+    })
+}
+
+fn key_validation_from_str(validation: &str) -> KeyValidation {
+    match validation {
+        "report" => KeyValidation::Report,
+        "skip" => KeyValidation::Skip,
+        "sanitize" => KeyValidation::Sanitize,
+        _ => panic!("Invalid key validation"),
+    }
+}
+
+fn case_collisions_from_str(case_collisions: &str) -> CaseCollisions {
+    match case_collisions {
+        "warn" => CaseCollisions::Warn,
+        "abort" => CaseCollisions::Abort,
+        _ => panic!("Invalid case collision handling"),
+    }
+}
+
+fn object_order_from_str(order: &str) -> ObjectOrder {
+    match order {
+        "key" => ObjectOrder::Key,
+        "size-desc" => ObjectOrder::SizeDesc,
+        "size-asc" => ObjectOrder::SizeAsc,
+        _ => panic!("Invalid object order"),
+    }
+}
+
+fn failure_count_from_str(count: &str) -> FailureCount {
+    match count {
+        "consecutive" => FailureCount::Consecutive,
+        "total" => FailureCount::Total,
+        _ => panic!("Invalid failure count"),
+    }
+}
+
+/// Reads the settings of the run from the arguments, and from the environment variables of the
+/// settings without an argument.
+async fn run_options(cli: &Cli) -> RunOptions {
+    let key_validation = env::var("VALIDATE_KEYS")
+        .ok()
+        .map(|validation| key_validation_from_str(&validation));
+    let key_checks = env::var("KEY_CHECKS")
+        .map(|checks| checks.split(',').map(KeyCheck::from_name).collect())
+        .unwrap_or(KeyCheck::ALL.to_vec());
+    let key_rules = KeyRules::new(
+        env::var("DATE_PARTITION").ok(),
+        env::var("KEY_TEMPLATE").ok().as_deref(),
+        if matches!(key_validation, Some(KeyValidation::Sanitize)) {
+            key_checks.clone()
+        } else {
+            vec![]
+        },
+    );
+
+    let mut prefixes = env::var("PREFIX_LIST_FILE")
+        .ok()
+        .map(|path| read_key_list(&path));
+    if let Some(object_prefixes) = env_list("OBJECT_PREFIX") {
+        prefixes.get_or_insert_default().extend(object_prefixes);
+    }
+
+    let acl = env::var("OBJECT_ACL").ok().map(|acl| {
+        if !ObjectCannedAcl::values().contains(&acl.as_str()) {
+            panic!("Invalid OBJECT_ACL: {}", acl);
+        }
+        ObjectCannedAcl::from(acl.as_str())
+    });
+    let upload_options = UploadOptions {
+        request_payer: env_flag("REQUEST_PAYER").then_some(RequestPayer::Requester),
+        if_none_match: env_flag("IF_NONE_MATCH"),
+        acl,
+        checksums: env_flag("CHECKSUMS"),
+        part_size: env::var("S3COPY_PART_SIZE")
+            .map(|size| {
+                let part_size = parse_size(&size)
+                    .unwrap_or_else(|| panic!("Invalid S3COPY_PART_SIZE: {}", size))
+                    as usize;
+                if part_size < MIN_PART_SIZE {
+                    panic!("S3COPY_PART_SIZE must be at least 5MiB: {}", size);
+                }
+                part_size
+            })
+            .unwrap_or(MIN_PART_SIZE),
+        multipart_threshold: env::var("S3COPY_MULTIPART_THRESHOLD")
+            .map(|size| {
+                parse_size(&size)
+                    .unwrap_or_else(|| panic!("Invalid S3COPY_MULTIPART_THRESHOLD: {}", size))
+                    as usize
+            })
+            .unwrap_or(MIN_PART_SIZE),
+        part_size_tiers: env::var("PART_SIZE_TIERS")
+            .map(|tiers| parse_part_size_tiers(&tiers))
+            .unwrap_or_default(),
+        max_concurrent_parts: env::var("MAX_CONCURRENT_PARTS")
+            .map(|parts| {
+                parts
+                    .parse()
+                    .ok()
+                    .filter(|parts| *parts > 0)
+                    .expect("MAX_CONCURRENT_PARTS must be a positive number")
+            })
+            .unwrap_or(8),
+        adaptive_part_concurrency: env_flag("ADAPTIVE_PART_CONCURRENCY"),
+        part_attempts: env::var("PART_ATTEMPTS")
+            .map(|attempts| {
+                attempts
+                    .parse()
+                    .ok()
+                    .filter(|attempts| *attempts > 0)
+                    .expect("PART_ATTEMPTS must be a positive number")
+            })
+            .unwrap_or(5),
+        storage_class_rules: env::var("STORAGE_CLASS_BY_SIZE")
+            .map(|rules| parse_storage_class_rules(&rules))
+            .unwrap_or_default(),
+        preserve_storage_class: env_flag("PRESERVE_STORAGE_CLASS"),
+        storage_class_map: env::var("STORAGE_CLASS_MAP")
+            .ok()
+            .map(|path| StorageClassMap::load(&path)),
+        verify_size: env_flag("VERIFY_SIZE"),
+        memory_budget: cli.max_inflight_bytes.map(MemoryBudget::new),
+        transfer_limit: cli
+            .max_transfers
+            .map(|transfers| RequestLimit::new(transfers as usize)),
+        head_limit: cli
+            .head_concurrency
+            .map(|requests| RequestLimit::new(requests as usize)),
+        open_uploads: OpenUploads::default(),
+        strict: env_flag("STRICT"),
+    };
+
+    RunOptions {
+        buckets: cli.config.as_deref().map(Config::load),
+        include_buckets: env_list("INCLUDE_BUCKETS"),
+        exclude_buckets: env_list("EXCLUDE_BUCKETS").unwrap_or_default(),
+        new_region: cli.new_region.clone(),
+        region_fallback: env_flag("REGION_FALLBACK"),
+        bucket_suffix: cli.bucket_suffix.clone(),
+        fail_fast_on_bucket_error: env_flag("FAIL_FAST_ON_BUCKET_ERROR"),
+        dry_run: cli.dry_run,
+        strict: env_flag("STRICT"),
+        abort_on_failures: cli.abort_on_failures.map(|failures| failures as usize),
+        failure_count: failure_count_from_str(&cli.abort_on_failures_count),
+        copy_bucket_notifications: env_flag("COPY_BUCKET_NOTIFICATIONS"),
+        notification_arn_map: env::var("NOTIFICATION_ARN_MAP")
+            .map(|path| read_arn_map(&path))
+            .unwrap_or_default(),
+        key_validation,
+        key_checks,
+        key_rules,
+        case_collisions: env::var("DETECT_CASE_COLLISIONS")
+            .ok()
+            .map(|case_collisions| case_collisions_from_str(&case_collisions)),
+        object_order: object_order_from_str(
+            env::var("OBJECT_ORDER")
+                .unwrap_or("key".to_string())
+                .as_str(),
+        ),
+        prefixes: prefixes.map(dedup_prefixes),
+        deny_keys: env::var("DENY_KEYS_FILE")
+            .map(|path| read_key_list(&path))
+            .unwrap_or_default(),
+        list_page_attempts: env::var("LIST_PAGE_ATTEMPTS")
+            .map(|attempts| {
+                attempts
+                    .parse()
+                    .ok()
+                    .filter(|attempts| *attempts > 0)
+                    .expect("LIST_PAGE_ATTEMPTS must be a positive number")
+            })
+            .unwrap_or(3),
+        skip_unreadable_pages: env_flag("SKIP_UNREADABLE_PAGES"),
+        needs_restore_file: env::var("SKIP_GLACIER_WITHOUT_RESTORE").ok(),
+        max_object_count: env::var("MAX_OBJECT_COUNT_PER_BUCKET").ok().map(|count| {
+            count
+                .parse::<usize>()
+                .expect("MAX_OBJECT_COUNT_PER_BUCKET must be a number")
+        }),
+        max_object_count_warn_only: env_flag("MAX_OBJECT_COUNT_WARN_ONLY"),
+        require_empty_destination: env_flag("REQUIRE_EMPTY_DESTINATION"),
+        preserve_ownership_metadata: env_flag("PRESERVE_OWNERSHIP_METADATA"),
+        upload_options,
+        dedup: env_flag("DEDUP"),
+        dedup_destination: env_flag("DEDUP_DESTINATION"),
+        plan_format: PlanFormat::from_name(
+            &env::var("DRY_RUN_FORMAT").unwrap_or("text".to_string()),
+        ),
+        plan_output: env::var("DRY_RUN_OUTPUT").ok(),
+        plan_to_stderr: cli.json,
+        auto_shard: env_flag("AUTO_SHARD"),
+        object_concurrency: env::var("OBJECT_CONCURRENCY")
+            .map(|concurrency| {
+                concurrency
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|concurrency| *concurrency > 0)
+                    .expect("OBJECT_CONCURRENCY must be a positive number")
+            })
+            .unwrap_or(16),
+        retention_only: env_flag("RETENTION_ONLY"),
+        abort_stale_uploads_older_than: env::var("ABORT_STALE_UPLOADS_OLDER_THAN_HOURS")
+            .map(|hours| {
+                Duration::from_secs(
+                    hours
+                        .parse::<u64>()
+                        .expect("ABORT_STALE_UPLOADS_OLDER_THAN_HOURS must be a number")
+                        * 60
+                        * 60,
+                )
+            })
+            .ok(),
+        watermark_file: env::var("WATERMARK_FILE").ok(),
+        manifest_file: env::var("MANIFEST_FILE").ok(),
+        state_file: cli.state_file.clone(),
+        pause_file: env::var("PAUSE_FILE").ok(),
+        exit_on_idle: env::var("EXIT_ON_IDLE").ok().map(|duration| {
+            parse_duration(&duration)
+                .unwrap_or_else(|| panic!("Invalid EXIT_ON_IDLE: {}", duration))
+        }),
+        max_bytes: env::var("MAX_BYTES")
+            .ok()
+            .map(|size| parse_size(&size).unwrap_or_else(|| panic!("Invalid MAX_BYTES: {}", size))),
+        metadata_key_rewrites: env::var("REWRITE_METADATA_KEYS")
+            .ok()
+            .map(|path| read_metadata_key_rewrites(&path)),
+        server_side_copy: cli.old_endpoint == cli.new_endpoint
+            && same_credentials(&cli.old_credentials, &cli.new_credentials).await,
+        bucket_operation_interval: Duration::from_millis(
+            env::var("BUCKET_OPERATION_INTERVAL_MS")
+                .map(|interval| {
+                    interval
+                        .parse()
+                        .expect("BUCKET_OPERATION_INTERVAL_MS must be a number")
+                })
+                .unwrap_or(200),
+        ),
+        presigned_urls: env::var("PRESIGNED_URLS").ok(),
+        presigned_bucket: env::var("PRESIGNED_BUCKET").ok(),
+        cancel: None,
     }
 }
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    // Without a terminal (or the feature) the run falls back to plain logs.
+    let tui = cli.tui && cfg!(feature = "tui") && io::stdout().is_terminal();
+    // Logs would draw over the dashboard, so they are dropped unless stderr is redirected.
+    let log = !tui || !io::stderr().is_terminal();
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    // Logs go to stderr, so stdout only carries the summary.
+    let logs = tracing_subscriber::fmt::layer().with_writer(move || -> Box<dyn Write> {
+        if log {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::sink())
+        }
+    });
+    #[cfg(feature = "otlp")]
+    let (spans, tracer_provider) = match cli.otlp_endpoint.as_deref().map(otlp::layer) {
+        Some((layer, provider)) => (Some(layer), Some(provider)),
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otlp"))]
+    let spans: Option<tracing_subscriber::layer::Identity> = None;
+    let subscriber = tracing_subscriber::registry().with(filter).with(spans);
+    if env::var("LOG_FORMAT").is_ok_and(|format| format == "json") {
+        subscriber.with(logs.json()).init();
+    } else {
+        subscriber.with(logs).init();
+    }
+
+    if cli.tui && !tui {
+        warn!("--tui needs a terminal and the tui feature, logging instead");
+    }
+    if cli.otlp_endpoint.is_some() && cfg!(not(feature = "otlp")) {
+        warn!("--otlp-endpoint needs the otlp feature, no spans are exported");
+    }
+    // Quitting the dashboard cancels the run, which still ends with the summary.
+    let cancel = Arc::new(Notify::new());
+    #[cfg(feature = "tui")]
+    let (progress, dashboard) = if tui {
+        let (progress, events) = Progress::channel();
+        (
+            progress,
+            Some(tokio::spawn(tui::run(events, cancel.clone()))),
+        )
+    } else {
+        (Progress::default(), None)
+    };
+    #[cfg(not(feature = "tui"))]
+    let progress = Progress::default();
+
     let old_client = get_client(
-        EnvConfigFiles::builder()
-            .with_file(EnvConfigFileKind::Credentials, ".old.credentials")
-            .build(),
-        region_from_str(
-            env::var("OLD_AWS_REGION")
-                .unwrap_or("us-east-1".to_string())
-                .as_str(),
-        ),
-        env::var("OLD_AWS_ENDPOINT_URL").ok().as_deref(),
+        credentials_file(&cli.old_credentials),
+        region_from_str(&cli.old_region),
+        cli.old_endpoint.as_deref(),
+        &cli.old_settings(),
     )
     .await;
-
     let new_client = get_client(
-        EnvConfigFiles::builder()
-            .with_file(EnvConfigFileKind::Credentials, ".new.credentials")
-            .build(),
-        region_from_str(
-            env::var("NEW_AWS_REGION")
-                .unwrap_or("us-east-1".to_string())
-                .as_str(),
-        ),
-        env::var("NEW_AWS_ENDPOINT_URL").ok().as_deref(),
+        credentials_file(&cli.new_credentials),
+        region_from_str(&cli.new_region),
+        cli.new_endpoint.as_deref(),
+        &cli.new_settings(),
     )
     .await;
+    let options = RunOptions {
+        cancel: tui.then_some(cancel),
+        ..run_options(&cli).await
+    };
 
-    let buckets = old_client
-        .list_buckets()
-        .send()
-        .await
-        .unwrap()
-        .buckets
-        .unwrap();
-
-    for bucket in buckets {
-        let bucket_name = bucket.name.as_deref().unwrap();
-        println!("Bucket: {}", bucket_name);
-
-        let mut new_bucket_name = bucket_name.to_string();
-
-        if let Err(e) = new_client
-            .create_bucket()
-            .bucket(&new_bucket_name)
-            .send()
-            .await
-        {
-            if format!("{:?}", e).contains("BucketAlreadyExists") {
-                new_bucket_name += &env::var("NEW_BUCKET_SUFFIX").expect(
-                    "NEW_BUCKET_SUFFIX must be set to avoid conflicts with existing buckets",
-                );
-                let _ = new_client
-                    .create_bucket()
-                    .bucket(&new_bucket_name)
-                    .send()
-                    .await;
-            } else {
-                panic!("{:?}", e);
-            }
+    let summary = run(&old_client, &new_client, &options, &progress).await;
+    #[cfg(feature = "otlp")]
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(e) = tracer_provider.shutdown() {
+            warn!("Failed to export the last spans: {}", e);
         }
+    }
+    // Closing the stream lets the dashboard finish its last frame and restore the terminal
+    // before the summary is printed.
+    drop(progress);
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        dashboard.await.unwrap();
+    }
+    if cli.json {
+        println!("{}", summary.to_json());
+    } else {
+        summary.print();
+    }
+    // A run stopped by the circuit breaker is told apart from other failed runs.
+    if summary.failure_limit_reached {
+        process::exit(3);
+    }
+    if summary.aborted || !summary.failed_objects.is_empty() || !summary.unlisted_ranges.is_empty()
+    {
+        process::exit(1);
+    }
 
-        println!("New Bucket: {}", new_bucket_name);
-
-        let mut migrated_objects = new_client
-            .list_objects_v2()
-            .max_keys(MAX_KEYS)
-            .bucket(&new_bucket_name)
-            .send()
-            .await
-            .unwrap()
-            .contents
-            .unwrap_or(vec![])
-            .iter()
-            .map(|object| object.key.clone().unwrap())
-            .collect::<Vec<String>>();
-        migrated_objects.sort_unstable();
-
-        let mut objects = old_client
-            .list_objects()
-            .max_keys(MAX_KEYS)
-            .bucket(bucket_name)
-            .send()
-            .await
-            .unwrap()
-            .contents
-            .unwrap_or(vec![]);
-        objects = objects
-            .iter()
-            .filter(|&object| {
-                migrated_objects
-                    .binary_search(&object.key.clone().unwrap())
-                    .is_err()
-            })
-            .cloned()
-            .collect::<Vec<Object>>();
+    if !cli.json {
+        println!("Done!");
+    }
+}
 
-        let constraint = BucketLocationConstraint::from(
-            env::var("NEW_AWS_REGION")
-                .unwrap_or("us-east-1".to_string())
-                .as_str(),
-        );
-        let bucket_config = CreateBucketConfiguration::builder()
-            .location_constraint(constraint)
-            .build();
-        let _ = new_client
-            .create_bucket()
-            .create_bucket_configuration(bucket_config)
-            .bucket(&new_bucket_name)
-            .send()
-            .await;
-
-        for object in objects {
-            let object_key = object.key.as_deref().unwrap();
-            println!("Object: {}", object_key);
-
-            let object = old_client
-                .get_object()
-                .bucket(bucket_name)
-                .key(object_key)
-                .send()
-                .await
-                .unwrap();
-
-            new_client
-                .put_object()
-                .bucket(&new_bucket_name)
-                .key(object_key)
-                .body(object.body.into())
-                .send()
-                .await
-                .unwrap();
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefixes(prefixes: &[&str]) -> HashSet<String> {
+        prefixes.iter().map(|prefix| prefix.to_string()).collect()
     }
 
-    println!("Done!");
+    #[test]
+    fn prefixes_covered_by_a_shorter_one_are_dropped() {
+        assert_eq!(
+            dedup_prefixes(prefixes(&["logs/2024/", "docs/", "logs/", "logs/2023/"])),
+            ["docs/", "logs/"]
+        );
+        assert_eq!(
+            dedup_prefixes(prefixes(&["logs", "logs-archive/", "log"])),
+            ["log"]
+        );
+        assert_eq!(
+            dedup_prefixes(prefixes(&["b/", "a/", "ab/"])),
+            ["a/", "ab/", "b/"]
+        );
+    }
 }
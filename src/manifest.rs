@@ -0,0 +1,120 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+};
+
+use aws_sdk_s3::types::Object;
+use serde::Serialize;
+
+use crate::checksum::Checksum;
+
+/// A JSON Lines file with one entry per copied object, written as each object completes so that
+/// an interrupted run leaves every finished entry behind.
+pub struct Manifest {
+    path: String,
+    file: File,
+}
+
+/// One line of the manifest.
+#[derive(Serialize)]
+struct Entry<'a> {
+    bucket: &'a str,
+    key: &'a str,
+    size: i64,
+    etag: Option<&'a str>,
+    checksum_algorithm: Option<&'static str>,
+    checksum: Option<&'a str>,
+}
+
+impl Manifest {
+    /// Opens the manifest for appending, so a resumed run adds to the entries of the previous
+    /// one. A last line cut off by an interrupted write is dropped first.
+    pub fn open(path: String) -> Self {
+        if let Ok(contents) = fs::read_to_string(&path) {
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                let complete = contents.rfind('\n').map_or(0, |i| i + 1);
+                fs::write(&path, &contents[..complete])
+                    .unwrap_or_else(|e| panic!("Failed to write {}: {}", path, e));
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap_or_else(|e| panic!("Failed to open {}: {}", path, e));
+        Manifest { path, file }
+    }
+
+    /// Appends the entry of an object copied to `bucket`/`key`.
+    pub fn record(
+        &mut self,
+        bucket: &str,
+        key: &str,
+        object: &Object,
+        checksum: Option<&Checksum>,
+    ) {
+        let entry = Entry {
+            bucket,
+            key,
+            size: object.size.unwrap_or_default(),
+            etag: object.e_tag.as_deref().map(|e_tag| e_tag.trim_matches('"')),
+            checksum_algorithm: checksum.map(Checksum::algorithm),
+            checksum: checksum.map(Checksum::value),
+        };
+        let line = serde_json::to_string(&entry).unwrap() + "\n";
+        self.file
+            .write_all(line.as_bytes())
+            .unwrap_or_else(|e| panic!("Failed to write {}: {}", self.path, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn entries_are_appended_as_json_lines_after_a_cut_off_line() {
+        let path = env::temp_dir().join(format!("s3copy-manifest-{}", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        fs::write(
+            &path,
+            "{\"bucket\":\"photos\",\"key\":\"cat.jpg\"}\n{\"bucket\":\"pho",
+        )
+        .unwrap();
+
+        let mut manifest = Manifest::open(path.clone());
+        manifest.record(
+            "photos",
+            "say \"meow\"\n.jpg",
+            &Object::builder().size(4).e_tag("\"abc\"").build(),
+            Some(&Checksum::Crc32("AAAAAA==".to_string())),
+        );
+        manifest.record("photos", "dog.jpg", &Object::builder().build(), None);
+        drop(manifest);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines = contents.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "{\"bucket\":\"photos\",\"key\":\"cat.jpg\"}");
+        let entry: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(
+            entry,
+            serde_json::json!({
+                "bucket": "photos",
+                "key": "say \"meow\"\n.jpg",
+                "size": 4,
+                "etag": "abc",
+                "checksum_algorithm": "CRC32",
+                "checksum": "AAAAAA==",
+            })
+        );
+        assert_eq!(
+            lines[2],
+            "{\"bucket\":\"photos\",\"key\":\"dog.jpg\",\"size\":0,\"etag\":null,\
+             \"checksum_algorithm\":null,\"checksum\":null}"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::{collections::HashMap, fs};
+
+use aws_sdk_s3::{types::NotificationConfiguration, Client};
+use tracing::{info, warn};
+
+/// Reads `source ARN<TAB>destination ARN` lines.
+pub fn read_arn_map(path: &str) -> HashMap<String, String> {
+    fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (source, destination) = line
+                .split_once('\t')
+                .unwrap_or_else(|| panic!("Invalid line in {}: {}", path, line));
+            (source.to_string(), destination.to_string())
+        })
+        .collect()
+}
+
+fn map_arn(arn_map: &HashMap<String, String>, bucket: &str, arn: &str) -> Option<String> {
+    let mapped = arn_map.get(arn).cloned();
+    if mapped.is_none() {
+        warn!(
+            "Notification target {} of {} has no mapping and is dropped",
+            arn, bucket
+        );
+    }
+    mapped
+}
+
+/// Copies the bucket's event notifications with their SNS, SQS and Lambda target ARNs rewritten
+/// through `arn_map`. Targets without a mapping are dropped with a warning, since the source
+/// account's ARNs would not be valid targets for the destination bucket. Fails when the source's
+/// notifications cannot be read; a destination that rejects them is only warned about. With
+/// `strict`, targets without a mapping and a rejection fail instead.
+pub async fn copy_notifications(
+    old_client: &Client,
+    new_client: &Client,
+    bucket: &str,
+    new_bucket: &str,
+    arn_map: &HashMap<String, String>,
+    strict: bool,
+) -> Result<(), String> {
+    let output = old_client
+        .get_bucket_notification_configuration()
+        .bucket(bucket)
+        .send()
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+    if strict {
+        let unmapped = output
+            .topic_configurations()
+            .iter()
+            .map(|topic| topic.topic_arn())
+            .chain(
+                output
+                    .queue_configurations()
+                    .iter()
+                    .map(|queue| queue.queue_arn()),
+            )
+            .chain(
+                output
+                    .lambda_function_configurations()
+                    .iter()
+                    .map(|function| function.lambda_function_arn()),
+            )
+            .filter(|arn| !arn_map.contains_key(*arn))
+            .collect::<Vec<_>>();
+        if !unmapped.is_empty() {
+            return Err(format!(
+                "notification targets have no mapping: {}",
+                unmapped.join(", ")
+            ));
+        }
+    }
+    let topics = output
+        .topic_configurations
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|mut topic| {
+            topic.topic_arn = map_arn(arn_map, bucket, &topic.topic_arn)?;
+            Some(topic)
+        })
+        .collect::<Vec<_>>();
+    let queues = output
+        .queue_configurations
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|mut queue| {
+            queue.queue_arn = map_arn(arn_map, bucket, &queue.queue_arn)?;
+            Some(queue)
+        })
+        .collect::<Vec<_>>();
+    let functions = output
+        .lambda_function_configurations
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|mut function| {
+            function.lambda_function_arn = map_arn(arn_map, bucket, &function.lambda_function_arn)?;
+            Some(function)
+        })
+        .collect::<Vec<_>>();
+    if topics.is_empty()
+        && queues.is_empty()
+        && functions.is_empty()
+        && output.event_bridge_configuration.is_none()
+    {
+        return Ok(());
+    }
+
+    info!(
+        "Notifications: {} topics, {} queues, {} functions{}",
+        topics.len(),
+        queues.len(),
+        functions.len(),
+        if output.event_bridge_configuration.is_some() {
+            ", EventBridge"
+        } else {
+            ""
+        }
+    );
+    let configuration = NotificationConfiguration::builder()
+        .set_topic_configurations(Some(topics))
+        .set_queue_configurations(Some(queues))
+        .set_lambda_function_configurations(Some(functions))
+        .set_event_bridge_configuration(output.event_bridge_configuration)
+        .build();
+    // S3 checks that it may publish to every target, which depends on the targets' own policies,
+    // so a rejection is reported without stopping the migration.
+    if let Err(e) = new_client
+        .put_bucket_notification_configuration()
+        .bucket(new_bucket)
+        .notification_configuration(configuration)
+        .send()
+        .await
+    {
+        if strict {
+            return Err(format!("{:?}", e));
+        }
+        warn!(
+            "Notifications of {} could not be copied: {:?}",
+            new_bucket, e
+        );
+    }
+    Ok(())
+}
+
+/// Replication rules refer to an IAM role and destination buckets that only exist in the
+/// source account, so they cannot be copied; a source with rules is only reported.
+pub async fn warn_replication(old_client: &Client, bucket: &str) {
+    if old_client
+        .get_bucket_replication()
+        .bucket(bucket)
+        .send()
+        .await
+        .is_ok()
+    {
+        warn!("Replication configuration of {} is not copied", bucket);
+    }
+}
@@ -0,0 +1,25 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+/// A layer exporting the spans of each bucket and object to the OTLP/HTTP endpoint (e.g.
+/// `http://localhost:4318/v1/traces`) in batches, and its provider, which has to be shut down
+/// before exiting so that the last batch is sent.
+pub fn layer<S>(endpoint: &str) -> (impl Layer<S>, SdkTracerProvider)
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .unwrap_or_else(|e| panic!("Failed to create the OTLP exporter for {}: {}", endpoint, e));
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("s3copy").build())
+        .build();
+    let tracer = provider.tracer("s3copy");
+    (tracing_opentelemetry::layer().with_tracer(tracer), provider)
+}
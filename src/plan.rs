@@ -0,0 +1,223 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+};
+
+use serde::Serialize;
+
+/// Format of the dry-run plan.
+#[derive(Clone, Default)]
+pub enum PlanFormat {
+    #[default]
+    Text,
+    Json,
+    Csv,
+}
+
+impl PlanFormat {
+    pub fn from_name(format: &str) -> Self {
+        match format {
+            "text" => PlanFormat::Text,
+            "json" => PlanFormat::Json,
+            "csv" => PlanFormat::Csv,
+            _ => panic!("DRY_RUN_FORMAT must be text, json or csv"),
+        }
+    }
+}
+
+/// What a run would do with an object, compared with the destination.
+pub enum PlanAction {
+    /// Not at the destination yet.
+    Copy,
+    /// At the destination with different content.
+    Overwrite,
+    /// At the destination with the same content.
+    Skip,
+}
+
+impl PlanAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PlanAction::Copy => "copy",
+            PlanAction::Overwrite => "overwrite",
+            PlanAction::Skip => "skip",
+        }
+    }
+}
+
+/// One line of a JSON plan.
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    action: &'static str,
+    bucket: &'a str,
+    key: &'a str,
+    destination_bucket: &'a str,
+    destination_key: &'a str,
+    size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes one line per object of a dry run, to a file, or to stdout unless it is kept for the
+/// JSON summary.
+pub struct Plan {
+    format: PlanFormat,
+    output: Box<dyn Write>,
+}
+
+impl Plan {
+    pub fn new(format: PlanFormat, path: Option<&str>, stderr: bool) -> Self {
+        let mut output: Box<dyn Write> = match path {
+            Some(path) => Box::new(
+                File::create(path).unwrap_or_else(|e| panic!("Failed to create {}: {}", path, e)),
+            ),
+            None if stderr => Box::new(io::stderr()),
+            None => Box::new(io::stdout()),
+        };
+        if let PlanFormat::Csv = format {
+            writeln!(
+                output,
+                "action,bucket,key,destination_bucket,destination_key,size,reason"
+            )
+            .unwrap();
+        }
+        Plan { format, output }
+    }
+
+    /// Writes the object's line. `reason` says why an object is skipped when that is not because
+    /// the destination already has it.
+    pub fn record(
+        &mut self,
+        action: PlanAction,
+        (bucket, key): (&str, &str),
+        (new_bucket, new_key): (&str, &str),
+        size: i64,
+        reason: Option<&str>,
+    ) {
+        let line = match self.format {
+            PlanFormat::Text => format!(
+                "{:<9} {}/{} -> {}/{} ({} bytes{})",
+                action.as_str(),
+                bucket,
+                key,
+                new_bucket,
+                new_key,
+                size,
+                reason
+                    .map(|reason| format!(", {}", reason))
+                    .unwrap_or_default()
+            ),
+            PlanFormat::Json => serde_json::to_string(&JsonEntry {
+                action: action.as_str(),
+                bucket,
+                key,
+                destination_bucket: new_bucket,
+                destination_key: new_key,
+                size,
+                reason,
+            })
+            .unwrap(),
+            PlanFormat::Csv => format!(
+                "{},{},{},{},{},{},{}",
+                action.as_str(),
+                csv_field(bucket),
+                csv_field(key),
+                csv_field(new_bucket),
+                csv_field(new_key),
+                size,
+                csv_field(reason.unwrap_or_default())
+            ),
+        };
+        writeln!(self.output, "{}", line).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        assert_eq!(csv_field("photos/cat.jpg"), "photos/cat.jpg");
+        assert_eq!(csv_field("cats, dogs.jpg"), "\"cats, dogs.jpg\"");
+        assert_eq!(csv_field("say \"meow\".txt"), "\"say \"\"meow\"\".txt\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn json_plans_have_one_object_per_line() {
+        let path = env::temp_dir().join(format!("s3copy-json-plan-{}", process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let mut plan = Plan::new(PlanFormat::Json, Some(&path), false);
+        plan.record(
+            PlanAction::Copy,
+            ("photos", "say \"meow\".jpg"),
+            ("archive", "photos/say \"meow\".jpg"),
+            4,
+            None,
+        );
+        plan.record(
+            PlanAction::Skip,
+            ("photos", ".env"),
+            ("archive", "photos/.env"),
+            12,
+            Some("denied"),
+        );
+        drop(plan);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "{\"action\":\"copy\",\"bucket\":\"photos\",\"key\":\"say \\\"meow\\\".jpg\",\
+             \"destination_bucket\":\"archive\",\"destination_key\":\"photos/say \\\"meow\\\".jpg\",\
+             \"size\":4}\n\
+             {\"action\":\"skip\",\"bucket\":\"photos\",\"key\":\".env\",\
+             \"destination_bucket\":\"archive\",\"destination_key\":\"photos/.env\",\
+             \"size\":12,\"reason\":\"denied\"}\n"
+        );
+    }
+
+    #[test]
+    fn csv_plans_start_with_a_header() {
+        let path = env::temp_dir().join(format!("s3copy-csv-plan-{}", process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let mut plan = Plan::new(PlanFormat::Csv, Some(&path), false);
+        plan.record(
+            PlanAction::Overwrite,
+            ("photos", "cats, dogs.jpg"),
+            ("archive", "photos/cats, dogs.jpg"),
+            4,
+            None,
+        );
+        plan.record(
+            PlanAction::Skip,
+            ("photos", ".env"),
+            ("archive", "photos/.env"),
+            12,
+            Some("denied"),
+        );
+        drop(plan);
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "action,bucket,key,destination_bucket,destination_key,size,reason\n\
+             overwrite,photos,\"cats, dogs.jpg\",archive,\"photos/cats, dogs.jpg\",4,\n\
+             skip,photos,.env,archive,photos/.env,12,denied\n"
+        );
+    }
+}
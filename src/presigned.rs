@@ -0,0 +1,55 @@
+use std::fs;
+
+use aws_sdk_s3::{operation::get_object::GetObjectOutput, primitives::ByteStream};
+use aws_smithy_types::body::SdkBody;
+use reqwest::header::{
+    HeaderName, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_TYPE, EXPIRES,
+};
+
+/// Reads `url<TAB>destination key` lines.
+pub fn read_presigned_urls(path: &str) -> Vec<(String, String)> {
+    fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (url, key) = line
+                .split_once('\t')
+                .unwrap_or_else(|| panic!("Invalid line in {}: {}", path, line));
+            (url.to_string(), key.to_string())
+        })
+        .collect()
+}
+
+/// Starts downloading a presigned URL and wraps the response as a `GetObjectOutput`, so that it
+/// can go through the same upload paths as objects read with the source client.
+pub async fn get_presigned(
+    http_client: &reqwest::Client,
+    url: &str,
+) -> Result<GetObjectOutput, String> {
+    let response = http_client
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        // The URL carries the signature, so keep it out of the error.
+        .map_err(|e| e.without_url().to_string())?;
+    let header = |name: HeaderName| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    Ok(GetObjectOutput::builder()
+        .set_content_length(response.content_length().map(|length| length as i64))
+        .set_content_type(header(CONTENT_TYPE))
+        .set_content_encoding(header(CONTENT_ENCODING))
+        .set_cache_control(header(CACHE_CONTROL))
+        .set_content_disposition(header(CONTENT_DISPOSITION))
+        .set_expires_string(header(EXPIRES))
+        .body(ByteStream::new(SdkBody::from_body_1_x(
+            reqwest::Body::from(response),
+        )))
+        .build())
+}
@@ -0,0 +1,79 @@
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// What the run reports as it goes, for displays that follow it live.
+pub enum ProgressEvent {
+    /// A bucket's copy starts with this many objects left to copy.
+    Bucket {
+        name: String,
+        objects: usize,
+        bytes: i64,
+    },
+    Started {
+        key: String,
+        size: i64,
+    },
+    /// The object is done; `bytes` is what was transferred, which is zero when it was skipped.
+    Finished {
+        key: String,
+        bytes: i64,
+    },
+    Failed {
+        key: String,
+        error: String,
+    },
+}
+
+/// Sends progress events to a display, or does nothing when there is none.
+#[derive(Clone, Default)]
+pub struct Progress {
+    sender: Option<UnboundedSender<ProgressEvent>>,
+}
+
+impl Progress {
+    /// A progress reporter and the receiver of its events.
+    pub fn channel() -> (Self, UnboundedReceiver<ProgressEvent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (
+            Progress {
+                sender: Some(sender),
+            },
+            receiver,
+        )
+    }
+
+    fn send(&self, event: ProgressEvent) {
+        if let Some(sender) = &self.sender {
+            // The display may already have been closed by the user.
+            let _ = sender.send(event);
+        }
+    }
+
+    pub fn bucket(&self, name: &str, objects: usize, bytes: i64) {
+        self.send(ProgressEvent::Bucket {
+            name: name.to_string(),
+            objects,
+            bytes,
+        });
+    }
+
+    pub fn started(&self, key: &str, size: i64) {
+        self.send(ProgressEvent::Started {
+            key: key.to_string(),
+            size,
+        });
+    }
+
+    pub fn finished(&self, key: &str, bytes: i64) {
+        self.send(ProgressEvent::Finished {
+            key: key.to_string(),
+            bytes,
+        });
+    }
+
+    pub fn failed(&self, key: &str, error: &str) {
+        self.send(ProgressEvent::Failed {
+            key: key.to_string(),
+            error: error.to_string(),
+        });
+    }
+}
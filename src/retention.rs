@@ -0,0 +1,221 @@
+use std::time::SystemTime;
+
+use aws_sdk_s3::{
+    primitives::DateTime,
+    types::{
+        ObjectLockLegalHold, ObjectLockLegalHoldStatus, ObjectLockRetention,
+        ObjectLockRetentionMode, RequestPayer,
+    },
+    Client,
+};
+use tracing::info;
+
+use crate::upload::UploadOptions;
+
+async fn get_retention(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    request_payer: Option<RequestPayer>,
+) -> Result<Option<ObjectLockRetention>, String> {
+    match client
+        .get_object_retention()
+        .set_request_payer(request_payer)
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(output) => Ok(output.retention),
+        Err(e) if format!("{:?}", e).contains("NoSuchObjectLockConfiguration") => Ok(None),
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
+
+async fn get_legal_hold(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    request_payer: Option<RequestPayer>,
+) -> Result<Option<ObjectLockLegalHold>, String> {
+    match client
+        .get_object_legal_hold()
+        .set_request_payer(request_payer)
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(output) => Ok(output.legal_hold),
+        Err(e) if format!("{:?}", e).contains("NoSuchObjectLockConfiguration") => Ok(None),
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
+
+fn retain_until(retention: &ObjectLockRetention) -> Option<&DateTime> {
+    retention.retain_until_date.as_ref()
+}
+
+/// Whether replacing `current` with `new` keeps the object locked at least as strictly and for
+/// at least as long. COMPLIANCE retention can never be relaxed to GOVERNANCE.
+fn extends(current: Option<&ObjectLockRetention>, new: &ObjectLockRetention) -> bool {
+    let Some(current) = current else {
+        return true;
+    };
+    if current.mode == Some(ObjectLockRetentionMode::Compliance)
+        && new.mode != Some(ObjectLockRetentionMode::Compliance)
+    {
+        return false;
+    }
+    match (retain_until(current), retain_until(new)) {
+        (Some(current), Some(new)) => new.secs() > current.secs(),
+        (None, Some(_)) => true,
+        _ => false,
+    }
+}
+
+/// Whether the retention has a retain-until date that has already passed. S3 rejects setting
+/// such a retention, and it no longer protects the source object anyway.
+fn is_expired(retention: &ObjectLockRetention, now: SystemTime) -> bool {
+    retain_until(retention).is_some_and(|retain_until| {
+        SystemTime::try_from(*retain_until).is_ok_and(|retain_until| retain_until <= now)
+    })
+}
+
+fn is_on(legal_hold: Option<&ObjectLockLegalHold>) -> bool {
+    legal_hold.is_some_and(|legal_hold| legal_hold.status == Some(ObjectLockLegalHoldStatus::On))
+}
+
+/// Applies the source object's Object Lock retention and legal hold to an already-copied
+/// destination object without transferring its data. Returns whether anything was changed.
+///
+/// Locks are only ever added or extended: a legal hold is turned on when the source has one, but
+/// never turned off, so that a destination hold placed on purpose is not released. A retention
+/// whose date has passed is skipped, or fails the object with `strict`.
+pub async fn copy_retention(
+    old_client: &Client,
+    new_client: &Client,
+    bucket: &str,
+    key: &str,
+    new_bucket: &str,
+    new_key: &str,
+    options: &UploadOptions,
+) -> Result<bool, String> {
+    let request_payer = &options.request_payer;
+    let mut updated = false;
+
+    if let Some(retention) = get_retention(old_client, bucket, key, request_payer.clone()).await? {
+        let current = get_retention(new_client, new_bucket, new_key, request_payer.clone()).await?;
+        if is_expired(&retention, SystemTime::now()) {
+            if options.strict {
+                return Err(format!(
+                    "Retention of {} cannot be preserved (retain-until date has passed)",
+                    new_key
+                ));
+            }
+            info!(
+                "Retention skipped (retain-until date has passed): {}",
+                new_key
+            );
+        } else if extends(current.as_ref(), &retention) {
+            new_client
+                .put_object_retention()
+                .set_request_payer(request_payer.clone())
+                .bucket(new_bucket)
+                .key(new_key)
+                .retention(retention)
+                .send()
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+            updated = true;
+        } else {
+            info!(
+                "Retention kept (destination is already as strict): {}",
+                new_key
+            );
+        }
+    }
+
+    let legal_hold = get_legal_hold(old_client, bucket, key, request_payer.clone()).await?;
+    if is_on(legal_hold.as_ref()) {
+        let current =
+            get_legal_hold(new_client, new_bucket, new_key, request_payer.clone()).await?;
+        if !is_on(current.as_ref()) {
+            new_client
+                .put_object_legal_hold()
+                .set_request_payer(request_payer.clone())
+                .bucket(new_bucket)
+                .key(new_key)
+                .legal_hold(
+                    ObjectLockLegalHold::builder()
+                        .status(ObjectLockLegalHoldStatus::On)
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+            updated = true;
+        }
+    }
+
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn retention(mode: ObjectLockRetentionMode, retain_until: i64) -> ObjectLockRetention {
+        ObjectLockRetention::builder()
+            .mode(mode)
+            .retain_until_date(DateTime::from_secs(retain_until))
+            .build()
+    }
+
+    #[test]
+    fn extends_only_to_longer_or_stricter_retention() {
+        let governance = retention(ObjectLockRetentionMode::Governance, 2000);
+        let compliance = retention(ObjectLockRetentionMode::Compliance, 2000);
+        assert!(extends(None, &governance));
+        assert!(extends(
+            Some(&governance),
+            &retention(ObjectLockRetentionMode::Governance, 3000)
+        ));
+        assert!(!extends(
+            Some(&governance),
+            &retention(ObjectLockRetentionMode::Governance, 1000)
+        ));
+        assert!(!extends(
+            Some(&compliance),
+            &retention(ObjectLockRetentionMode::Governance, 3000)
+        ));
+        assert!(extends(
+            Some(&governance),
+            &retention(ObjectLockRetentionMode::Compliance, 3000)
+        ));
+    }
+
+    #[test]
+    fn retention_in_the_past_is_expired() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(2000);
+        assert!(is_expired(
+            &retention(ObjectLockRetentionMode::Governance, 1000),
+            now
+        ));
+        assert!(!is_expired(
+            &retention(ObjectLockRetentionMode::Governance, 3000),
+            now
+        ));
+        assert!(!is_expired(&ObjectLockRetention::builder().build(), now));
+    }
+
+    #[test]
+    fn only_a_hold_that_is_on_counts() {
+        let hold = |status| ObjectLockLegalHold::builder().status(status).build();
+        assert!(is_on(Some(&hold(ObjectLockLegalHoldStatus::On))));
+        assert!(!is_on(Some(&hold(ObjectLockLegalHoldStatus::Off))));
+        assert!(!is_on(None));
+    }
+}
@@ -0,0 +1,3525 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::Write,
+    ops::ControlFlow,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use aws_sdk_s3::{
+    types::{
+        BucketLocationConstraint, ChecksumMode, CreateBucketConfiguration, MetadataDirective,
+        Object, ObjectStorageClass, RequestPayer, StorageClass, TaggingDirective,
+    },
+    Client,
+};
+use futures::{
+    future,
+    stream::{FuturesUnordered, StreamExt},
+};
+use serde::Serialize;
+use tokio::{sync::Notify, time::Instant};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    checksum::Checksum,
+    client::{bucket_region, regional_client},
+    config::BucketConfig,
+    copy::{copy_source, object_tagging, server_side_copy},
+    key::{KeyCheck, KeyRules},
+    listing::{
+        list_destination_objects, list_source_objects, list_source_objects_sharded, ListingRetries,
+    },
+    manifest::Manifest,
+    notification::{copy_notifications, warn_replication},
+    plan::{Plan, PlanAction, PlanFormat},
+    progress::Progress,
+    retention::copy_retention,
+    state::State,
+    upload::{
+        checked_expires, list_multipart_uploads, multipart_upload, singlepart_upload,
+        with_user_metadata, StorageClassRule, UploadOptions, UploadOutcome, MAX_SINGLEPART_SIZE,
+    },
+    watermark::Watermarks,
+};
+
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What to do with keys that tend to break downstream systems.
+pub enum KeyValidation {
+    /// Count and log them, and copy them unchanged.
+    Report,
+    Skip,
+    /// Copy them with the problems removed from the destination key.
+    Sanitize,
+}
+
+/// What to do with destination keys that only differ in case.
+pub enum CaseCollisions {
+    Warn,
+    Abort,
+}
+
+/// The order in which the objects of a bucket are copied.
+#[derive(Default)]
+pub enum ObjectOrder {
+    #[default]
+    Key,
+    SizeDesc,
+    SizeAsc,
+}
+
+/// How failed objects are counted against `RunOptions::abort_on_failures`.
+#[derive(Clone, Copy, Default)]
+pub enum FailureCount {
+    /// Failures in a row, which an object copied or skipped starts over.
+    #[default]
+    Consecutive,
+    Total,
+}
+
+/// Settings of a run. The `s3copy` binary reads them from its arguments and environment
+/// variables, which the README lists.
+pub struct RunOptions {
+    /// Buckets to copy, or every bucket of the source when unset.
+    pub buckets: Option<Vec<BucketConfig>>,
+    /// Patterns of the bucket names to copy, in which `*` stands for any run of characters.
+    pub include_buckets: Option<Vec<String>>,
+    pub exclude_buckets: Vec<String>,
+    /// Region of the destination client, used for buckets whose region cannot be detected.
+    pub new_region: String,
+    pub region_fallback: bool,
+    /// Suffix added to a bucket name that is already taken at the destination.
+    pub bucket_suffix: Option<String>,
+    pub fail_fast_on_bucket_error: bool,
+    pub dry_run: bool,
+    pub strict: bool,
+    /// Ends the run once this many objects have failed, counted as `failure_count` says. The
+    /// objects in flight are finished first.
+    pub abort_on_failures: Option<usize>,
+    pub failure_count: FailureCount,
+    pub copy_bucket_notifications: bool,
+    /// Destination ARNs of the notification targets, by source ARN.
+    pub notification_arn_map: HashMap<String, String>,
+    pub key_validation: Option<KeyValidation>,
+    pub key_checks: Vec<KeyCheck>,
+    pub key_rules: KeyRules,
+    pub case_collisions: Option<CaseCollisions>,
+    pub object_order: ObjectOrder,
+    /// Only objects under these prefixes are copied. They must not overlap.
+    pub prefixes: Option<Vec<String>>,
+    pub deny_keys: HashSet<String>,
+    pub list_page_attempts: u32,
+    pub skip_unreadable_pages: bool,
+    /// File listing the archived objects that are skipped until restored.
+    pub needs_restore_file: Option<String>,
+    pub max_object_count: Option<usize>,
+    pub max_object_count_warn_only: bool,
+    pub require_empty_destination: bool,
+    pub preserve_ownership_metadata: bool,
+    pub upload_options: UploadOptions,
+    pub dedup: bool,
+    pub dedup_destination: bool,
+    pub plan_format: PlanFormat,
+    /// File the dry-run plan is written to, instead of stdout.
+    pub plan_output: Option<String>,
+    /// Writes the plan to stderr when there is no file, so that stdout only has the JSON summary.
+    pub plan_to_stderr: bool,
+    pub auto_shard: bool,
+    pub object_concurrency: usize,
+    pub retention_only: bool,
+    pub abort_stale_uploads_older_than: Option<Duration>,
+    pub watermark_file: Option<String>,
+    pub manifest_file: Option<String>,
+    pub state_file: Option<String>,
+    pub pause_file: Option<String>,
+    pub exit_on_idle: Option<Duration>,
+    pub max_bytes: Option<i64>,
+    /// New names of user metadata keys, or `None` to drop the key, by lower-case old name.
+    pub metadata_key_rewrites: Option<HashMap<String, Option<String>>>,
+    /// Whether the source and destination share the endpoint and credentials, so that objects
+    /// can be copied by the endpoint itself. Options that change objects on the way still read
+    /// and upload them.
+    pub server_side_copy: bool,
+    pub bucket_operation_interval: Duration,
+    /// File of `url<TAB>key` lines copied into `presigned_bucket` instead of the source buckets.
+    pub presigned_urls: Option<String>,
+    pub presigned_bucket: Option<String>,
+    /// Ends the run when notified. The copies in flight are dropped, and the summary holds what
+    /// was copied until then.
+    pub cancel: Option<Arc<Notify>>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            buckets: None,
+            include_buckets: None,
+            exclude_buckets: vec![],
+            new_region: "us-east-1".to_string(),
+            region_fallback: false,
+            bucket_suffix: None,
+            fail_fast_on_bucket_error: false,
+            dry_run: false,
+            strict: false,
+            abort_on_failures: None,
+            failure_count: FailureCount::default(),
+            copy_bucket_notifications: false,
+            notification_arn_map: HashMap::new(),
+            key_validation: None,
+            key_checks: KeyCheck::ALL.to_vec(),
+            key_rules: KeyRules::default(),
+            case_collisions: None,
+            object_order: ObjectOrder::default(),
+            prefixes: None,
+            deny_keys: HashSet::new(),
+            list_page_attempts: 3,
+            skip_unreadable_pages: false,
+            needs_restore_file: None,
+            max_object_count: None,
+            max_object_count_warn_only: false,
+            require_empty_destination: false,
+            preserve_ownership_metadata: false,
+            upload_options: UploadOptions::default(),
+            dedup: false,
+            dedup_destination: false,
+            plan_format: PlanFormat::default(),
+            plan_output: None,
+            plan_to_stderr: false,
+            auto_shard: false,
+            object_concurrency: 16,
+            retention_only: false,
+            abort_stale_uploads_older_than: None,
+            watermark_file: None,
+            manifest_file: None,
+            state_file: None,
+            pause_file: None,
+            exit_on_idle: None,
+            max_bytes: None,
+            metadata_key_rewrites: None,
+            server_side_copy: false,
+            bucket_operation_interval: Duration::from_millis(200),
+            presigned_urls: None,
+            presigned_bucket: None,
+            cancel: None,
+        }
+    }
+}
+
+/// Spaces out bucket-level requests, which S3 rate limits far more tightly than object requests.
+struct BucketOperationLimiter {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl BucketOperationLimiter {
+    fn new(interval: Duration) -> Self {
+        BucketOperationLimiter {
+            interval,
+            last: None,
+        }
+    }
+
+    async fn wait(&mut self) {
+        if let Some(last) = self.last {
+            tokio::time::sleep_until(last + self.interval).await;
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+/// Ends the run when no object has completed for `timeout`, catching stalls that per-request
+/// timeouts and retries do not. The timer only runs while the objects of a bucket are copied, so
+/// a long listing is not taken for a stall.
+struct IdleWatchdog {
+    timeout: Duration,
+    /// When the last object completed, or `None` while no objects are being copied.
+    last_progress: Mutex<Option<Instant>>,
+}
+
+impl IdleWatchdog {
+    fn new(timeout: Duration) -> Self {
+        IdleWatchdog {
+            timeout,
+            last_progress: Mutex::new(None),
+        }
+    }
+
+    /// Restarts the timer, starting it when the copy of a bucket's objects begins.
+    fn progress(&self) {
+        *self.last_progress.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn stop(&self) {
+        *self.last_progress.lock().unwrap() = None;
+    }
+
+    /// Returns once the timer has run out.
+    async fn idle(&self) {
+        loop {
+            tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+            if self
+                .last_progress
+                .lock()
+                .unwrap()
+                .is_some_and(|last_progress| last_progress.elapsed() > self.timeout)
+            {
+                return;
+            }
+        }
+    }
+}
+
+async fn wait_while_paused(pause_file: &str, idle_watchdog: Option<&IdleWatchdog>) {
+    if !Path::new(pause_file).exists() {
+        return;
+    }
+    info!("Paused: remove {} to resume", pause_file);
+    while Path::new(pause_file).exists() {
+        if let Some(idle_watchdog) = idle_watchdog {
+            idle_watchdog.progress();
+        }
+        tokio::time::sleep(PAUSE_CHECK_INTERVAL).await;
+    }
+    info!("Resumed");
+}
+
+/// Matches a bucket name against a pattern in which `*` stands for any run of characters.
+pub fn matches_glob(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.collect::<Vec<&str>>();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Aborts the bucket's multipart uploads started more than `older_than` ago, such as those left
+/// by a run that was killed mid-upload, and returns how many were (or would be) aborted.
+async fn abort_stale_uploads(
+    client: &Client,
+    bucket: &str,
+    older_than: Duration,
+    dry_run: bool,
+    request_payer: Option<RequestPayer>,
+) -> usize {
+    let uploads = match list_multipart_uploads(client, bucket, request_payer.clone()).await {
+        Ok(uploads) => uploads,
+        Err(e) => {
+            warn!("Failed to list multipart uploads: {}: {}", bucket, e);
+            return 0;
+        }
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let mut aborted = 0;
+    for upload in uploads {
+        if upload
+            .initiated
+            .is_none_or(|initiated| now - initiated.secs() <= older_than.as_secs() as i64)
+        {
+            continue;
+        }
+        let key = upload.key.unwrap_or_default();
+        let upload_id = upload.upload_id.unwrap_or_default();
+        if dry_run {
+            info!("Would abort stale upload: {} ({})", key, upload_id);
+        } else {
+            match client
+                .abort_multipart_upload()
+                .set_request_payer(request_payer.clone())
+                .bucket(bucket)
+                .key(&key)
+                .upload_id(&upload_id)
+                .send()
+                .await
+            {
+                Ok(_) => info!("Aborted stale upload: {} ({})", key, upload_id),
+                Err(e) => {
+                    warn!(
+                        "Failed to abort stale upload: {} ({}): {:?}",
+                        key, upload_id, e
+                    );
+                    continue;
+                }
+            }
+        }
+        aborted += 1;
+    }
+    aborted
+}
+
+/// Whether the destination object already holds the source object's content. Multipart ETags
+/// are not MD5s of the content and depend on the part size, so only the sizes are compared when
+/// either side has one.
+pub fn is_same_content(object: &Object, destination: &Object) -> bool {
+    if object.size != destination.size {
+        return false;
+    }
+    match (&object.e_tag, &destination.e_tag) {
+        (Some(e_tag), Some(destination_e_tag))
+            if !e_tag.contains('-') && !destination_e_tag.contains('-') =>
+        {
+            e_tag == destination_e_tag
+        }
+        _ => true,
+    }
+}
+
+fn sort_objects(objects: &mut [(Object, String)], order: &ObjectOrder) {
+    match order {
+        ObjectOrder::Key => objects.sort_by(|(a, _), (b, _)| a.key.cmp(&b.key)),
+        ObjectOrder::SizeDesc => objects.sort_by_key(|(object, _)| Reverse(object.size)),
+        ObjectOrder::SizeAsc => objects.sort_by_key(|(object, _)| object.size),
+    }
+}
+
+/// How the copy of one object ended, applied to the run's counts once it is finished.
+enum CopyOutcome {
+    /// Copied within the destination from an object with the same content.
+    Duplicate,
+    Uploaded {
+        multipart: bool,
+        checksum: Option<Checksum>,
+    },
+    AlreadyPresent,
+}
+
+/// Objects that could not be copied.
+struct Failures {
+    objects: Vec<(String, String)>,
+    consecutive: usize,
+}
+
+impl Failures {
+    fn new() -> Self {
+        Failures {
+            objects: vec![],
+            consecutive: 0,
+        }
+    }
+
+    fn record(&mut self, bucket: &str, key: &str, error: String) {
+        error!(bucket, key, error = %error, "Failed");
+        self.objects.push((format!("{}/{}", bucket, key), error));
+        self.consecutive += 1;
+    }
+
+    fn succeeded(&mut self) {
+        self.consecutive = 0;
+    }
+
+    /// Whether ABORT_ON_FAILURES has been reached, logging it when it has.
+    fn limit_reached(&self, options: &RunOptions) -> bool {
+        let Some(limit) = options.abort_on_failures else {
+            return false;
+        };
+        let (count, counted) = match options.failure_count {
+            FailureCount::Consecutive => (self.consecutive, "consecutive failures"),
+            FailureCount::Total => (self.objects.len(), "failures"),
+        };
+        if count < limit {
+            return false;
+        }
+        error!("Aborted: {} {} (ABORT_ON_FAILURES)", count, counted);
+        true
+    }
+
+    /// Whether the run stops here: at the first failure with STRICT, or once ABORT_ON_FAILURES
+    /// has been reached. The summary is marked as aborted, with the reason, the first time.
+    fn stop(&self, options: &RunOptions, summary: &mut RunSummary) -> bool {
+        if summary.stopped_by_failures() {
+            return true;
+        }
+        if options.strict && !self.objects.is_empty() {
+            error!("Aborted: an object failed (STRICT)");
+            summary.aborted = true;
+            summary.strict_failure = true;
+            return true;
+        }
+        if !self.limit_reached(options) {
+            return false;
+        }
+        summary.aborted = true;
+        summary.failure_limit_reached = true;
+        true
+    }
+}
+
+/// Counts collected over a run. The optional counts are only set when their option is.
+#[derive(Default)]
+pub struct RunSummary {
+    pub buckets: usize,
+    pub objects_copied: usize,
+    pub objects_skipped: usize,
+    pub singlepart_objects: usize,
+    pub multipart_objects: usize,
+    /// Source `bucket/key` of each failed object with its error.
+    pub failed_objects: Vec<(String, String)>,
+    pub bytes: i64,
+    /// Buckets given up on, with the reason.
+    pub skipped_buckets: Vec<(String, String)>,
+    pub dedup_saved_bytes: Option<i64>,
+    pub problematic_keys: Option<usize>,
+    pub needs_restore: Option<usize>,
+    /// Key ranges whose listing was given up on.
+    pub unlisted_ranges: Vec<String>,
+    pub retention_updated: Option<usize>,
+    pub stale_uploads_aborted: Option<usize>,
+    /// Objects per storage class set on the destination.
+    pub storage_classes: BTreeMap<String, usize>,
+    /// Objects per source storage class missing from the storage class map.
+    pub unmapped_storage_classes: BTreeMap<String, usize>,
+    /// Objects per rewritten metadata key, with the key's new name or `None` if dropped.
+    pub rewritten_metadata_keys: Vec<(String, Option<String>, usize)>,
+    pub aborted: bool,
+    /// The run was aborted at ABORT_ON_FAILURES.
+    pub failure_limit_reached: bool,
+    /// The run was stopped by STRICT, at the first failed object or at bucket settings that could
+    /// not be copied in full.
+    pub strict_failure: bool,
+    pub dry_run: bool,
+    pub duration: Duration,
+}
+
+impl RunSummary {
+    fn stopped_by_failures(&self) -> bool {
+        self.failure_limit_reached || self.strict_failure
+    }
+
+    pub fn print(&self) {
+        for (bucket_name, reason) in &self.skipped_buckets {
+            println!("Skipped bucket: {}: {}", bucket_name, reason);
+        }
+        if let Some(dedup_saved_bytes) = self.dedup_saved_bytes {
+            println!("Dedup saved: {} bytes", dedup_saved_bytes);
+        }
+        if let Some(problematic_keys) = self.problematic_keys {
+            println!("Problematic keys: {}", problematic_keys);
+        }
+        if let Some(needs_restore) = self.needs_restore {
+            println!("Needs restore: {} objects", needs_restore);
+        }
+        for range in &self.unlisted_ranges {
+            println!("Unlisted: {}", range);
+        }
+        if let Some(retention_updated) = self.retention_updated {
+            println!("Retention updated: {} objects", retention_updated);
+        }
+        if let Some(stale_uploads_aborted) = self.stale_uploads_aborted {
+            println!("Stale uploads aborted: {}", stale_uploads_aborted);
+        }
+        for (storage_class, objects) in &self.storage_classes {
+            println!("Storage class {}: {} objects", storage_class, objects);
+        }
+        for (storage_class, objects) in &self.unmapped_storage_classes {
+            println!(
+                "Unmapped storage class {}: {} objects",
+                storage_class, objects
+            );
+        }
+        for (old, new, objects) in &self.rewritten_metadata_keys {
+            match new {
+                Some(new) => println!("Metadata key {} -> {}: {} objects", old, new, objects),
+                None => println!("Metadata key {} dropped: {} objects", old, objects),
+            }
+        }
+        println!("Buckets: {}", self.buckets);
+        if self.dry_run {
+            println!(
+                "Objects: {} would be copied, {} skipped",
+                self.objects_copied, self.objects_skipped
+            );
+            println!("Would transfer: {} bytes", self.bytes);
+            return;
+        }
+        for (object, error) in &self.failed_objects {
+            println!("Failed object: {}: {}", object, error);
+        }
+        if self.failure_limit_reached {
+            println!("Aborted: ABORT_ON_FAILURES reached");
+        }
+        if self.strict_failure {
+            println!("Aborted: an object failed (STRICT)");
+        }
+        println!(
+            "Objects: {} copied, {} skipped, {} failed",
+            self.objects_copied,
+            self.objects_skipped,
+            self.failed_objects.len()
+        );
+        println!(
+            "Uploads: {} singlepart, {} multipart",
+            self.singlepart_objects, self.multipart_objects
+        );
+        println!("Transferred: {} bytes", self.bytes);
+        println!("Duration: {:.1}s", self.duration.as_secs_f64());
+    }
+
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct FailedObject<'a> {
+            object: &'a str,
+            error: &'a str,
+        }
+
+        #[derive(Serialize)]
+        struct JsonSummary<'a> {
+            buckets: usize,
+            objects_copied: usize,
+            objects_skipped: usize,
+            objects_failed: usize,
+            singlepart: usize,
+            multipart: usize,
+            bytes: i64,
+            failed_objects: Vec<FailedObject<'a>>,
+            unlisted_ranges: &'a [String],
+            dry_run: bool,
+            aborted: bool,
+            failure_limit_reached: bool,
+            strict_failure: bool,
+            duration_secs: f64,
+        }
+
+        serde_json::to_string(&JsonSummary {
+            buckets: self.buckets,
+            objects_copied: self.objects_copied,
+            objects_skipped: self.objects_skipped,
+            objects_failed: self.failed_objects.len(),
+            singlepart: self.singlepart_objects,
+            multipart: self.multipart_objects,
+            bytes: self.bytes,
+            failed_objects: self
+                .failed_objects
+                .iter()
+                .map(|(object, error)| FailedObject { object, error })
+                .collect(),
+            unlisted_ranges: &self.unlisted_ranges,
+            dry_run: self.dry_run,
+            aborted: self.aborted,
+            failure_limit_reached: self.failure_limit_reached,
+            strict_failure: self.strict_failure,
+            // Milliseconds are plenty for a whole run.
+            duration_secs: (self.duration.as_secs_f64() * 1000.0).round() / 1000.0,
+        })
+        .unwrap()
+    }
+}
+
+/// Copies objects listed as `url<TAB>key` in the file from presigned GET URLs into
+/// `presigned_bucket`. A failed download is counted and the next URL is tried. A dry run only
+/// reads the size of each object from the response headers, and uploads nothing.
+#[cfg(feature = "presigned")]
+async fn copy_presigned(new_client: &Client, path: &str, options: &RunOptions) -> RunSummary {
+    use crate::presigned::{get_presigned, read_presigned_urls};
+
+    let bucket = options
+        .presigned_bucket
+        .as_deref()
+        .expect("PRESIGNED_BUCKET must be set with PRESIGNED_URLS");
+    let upload_options = &options.upload_options;
+    let http_client = reqwest::Client::new();
+    let mut failures = Failures::new();
+    let mut summary = RunSummary {
+        buckets: 1,
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+    for (url, key) in read_presigned_urls(path) {
+        let object = match get_presigned(&http_client, &url).await {
+            Ok(object) => object,
+            Err(e) => {
+                failures.record(bucket, &key, e);
+                if failures.stop(options, &mut summary) {
+                    break;
+                }
+                continue;
+            }
+        };
+        let object_size = object.content_length;
+        let multipart = upload_options.is_multipart(object_size);
+        let outcome = if options.dry_run {
+            info!(
+                key,
+                size = object_size,
+                mode = if multipart { "multipart" } else { "singlepart" },
+                "Would copy"
+            );
+            // Dropping the response ends the download after its headers.
+            Ok(UploadOutcome::Uploaded)
+        } else {
+            info!(
+                key,
+                size = object_size,
+                mode = if multipart { "multipart" } else { "singlepart" },
+                "Upload"
+            );
+            if multipart {
+                multipart_upload(new_client, bucket, &key, object, None, None, upload_options).await
+            } else {
+                singlepart_upload(new_client, bucket, &key, object, None, None, upload_options)
+                    .await
+            }
+        };
+        if outcome.is_ok() {
+            failures.succeeded();
+        }
+        match outcome {
+            Ok(UploadOutcome::Uploaded) => {
+                summary.bytes += object_size.unwrap_or_default();
+                summary.objects_copied += 1;
+                if multipart {
+                    summary.multipart_objects += 1;
+                } else {
+                    summary.singlepart_objects += 1;
+                }
+            }
+            Ok(UploadOutcome::AlreadyPresent) => {
+                info!("Skipped (already present): {}", key);
+                summary.objects_skipped += 1;
+            }
+            Err(e) => failures.record(bucket, &key, e),
+        }
+        if failures.stop(options, &mut summary) {
+            break;
+        }
+    }
+    summary.failed_objects = failures.objects;
+    summary
+}
+
+#[cfg(not(feature = "presigned"))]
+async fn copy_presigned(_: &Client, _: &str, _: &RunOptions) -> RunSummary {
+    panic!("PRESIGNED_URLS requires s3copy to be built with the presigned feature")
+}
+
+/// What the copy of every object of a bucket shares.
+struct BucketCopy<'a> {
+    old_client: &'a Client,
+    new_client: &'a Client,
+    bucket: &'a str,
+    new_bucket: &'a str,
+    options: &'a RunOptions,
+    upload_options: &'a UploadOptions,
+    server_side: bool,
+    stored_checksum: bool,
+}
+
+impl BucketCopy<'_> {
+    /// Renames the user metadata keys of METADATA_KEY_REWRITES, adding the old names of the keys
+    /// it finds to `rewritten_keys`.
+    fn rewrite_metadata(
+        &self,
+        user_metadata: Option<&mut HashMap<String, String>>,
+        rewritten_keys: &mut Vec<String>,
+    ) {
+        let (Some(rewrites), Some(user_metadata)) =
+            (&self.options.metadata_key_rewrites, user_metadata)
+        else {
+            return;
+        };
+        // Remove every rewritten key before inserting, so that renames like a -> b and b -> c do
+        // not depend on the order of the map.
+        let mut renamed = vec![];
+        for (old, new) in rewrites {
+            if let Some(value) = user_metadata.remove(old) {
+                renamed.extend(new.clone().map(|new| (new, value)));
+                rewritten_keys.push(old.clone());
+            }
+        }
+        user_metadata.extend(renamed);
+    }
+
+    /// Copies the object to `new_key`, within the destination from `copied_key` when that holds
+    /// the same content. Metadata keys renamed on the way are added to `rewritten_keys`.
+    async fn copy_object(
+        &self,
+        object: &Object,
+        new_key: &str,
+        copied_key: Option<String>,
+        metadata: Option<HashMap<String, String>>,
+        storage_class: Option<StorageClass>,
+        rewritten_keys: &mut Vec<String>,
+    ) -> Result<CopyOutcome, String> {
+        let object_key = object.key.as_deref().unwrap();
+        let object_size = object.size.unwrap_or_default();
+        let request_payer = &self.upload_options.request_payer;
+        if let Some(copied_key) = copied_key {
+            // The copy it is made from only shares the content, so the duplicate's own headers,
+            // metadata and tags replace those of the copy, like an upload would set them.
+            let mut head = {
+                let _head = self.upload_options.reserve_head().await;
+                self.old_client
+                    .head_object()
+                    .set_request_payer(request_payer.clone())
+                    .bucket(self.bucket)
+                    .key(object_key)
+                    .send()
+                    .await
+                    .map_err(|e| format!("{:?}", e))?
+            };
+            self.rewrite_metadata(head.metadata.as_mut(), rewritten_keys);
+            let tagging = object_tagging(
+                self.old_client,
+                self.bucket,
+                object_key,
+                self.upload_options,
+            )
+            .await?;
+            self.new_client
+                .copy_object()
+                .set_request_payer(request_payer.clone())
+                .copy_source(copy_source(self.new_bucket, &copied_key))
+                .bucket(self.new_bucket)
+                .key(new_key)
+                .set_acl(self.upload_options.acl.clone())
+                .set_storage_class(storage_class)
+                .tagging_directive(TaggingDirective::Replace)
+                .set_tagging(tagging)
+                .metadata_directive(MetadataDirective::Replace)
+                .set_content_type(head.content_type)
+                .set_content_encoding(head.content_encoding)
+                .set_cache_control(head.cache_control)
+                .set_content_disposition(head.content_disposition)
+                .set_expires(checked_expires(
+                    head.expires_string.as_deref(),
+                    new_key,
+                    self.upload_options,
+                )?)
+                .set_metadata(with_user_metadata(head.metadata.as_ref(), metadata))
+                .send()
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+            return Ok(CopyOutcome::Duplicate);
+        }
+
+        if self.server_side {
+            info!(key = object_key, size = object_size, "Server-side copy");
+            let multipart = server_side_copy(
+                self.old_client,
+                self.new_client,
+                self.bucket,
+                object,
+                self.new_bucket,
+                new_key,
+                self.upload_options,
+            )
+            .await?;
+            return Ok(CopyOutcome::Uploaded {
+                multipart,
+                checksum: None,
+            });
+        }
+
+        let mut source_object = self
+            .old_client
+            .get_object()
+            .set_request_payer(request_payer.clone())
+            .bucket(self.bucket)
+            .key(object_key)
+            .set_checksum_mode(
+                self.upload_options
+                    .checksums
+                    .then_some(ChecksumMode::Enabled),
+            )
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+
+        self.rewrite_metadata(source_object.metadata.as_mut(), rewritten_keys);
+
+        // The tag count comes with the object, so untagged objects need no extra request.
+        let tagging = if source_object
+            .tag_count
+            .is_some_and(|tag_count| tag_count > 0)
+        {
+            object_tagging(
+                self.old_client,
+                self.bucket,
+                object_key,
+                self.upload_options,
+            )
+            .await?
+        } else {
+            None
+        };
+
+        let checksum = self
+            .stored_checksum
+            .then(|| Checksum::stored(&source_object))
+            .flatten();
+        let multipart = self
+            .upload_options
+            .is_multipart(source_object.content_length);
+        info!(
+            key = object_key,
+            size = object_size,
+            mode = if multipart { "multipart" } else { "singlepart" },
+            "Upload"
+        );
+        let outcome = if multipart {
+            multipart_upload(
+                self.new_client,
+                self.new_bucket,
+                new_key,
+                source_object,
+                metadata,
+                tagging,
+                self.upload_options,
+            )
+            .await?
+        } else {
+            singlepart_upload(
+                self.new_client,
+                self.new_bucket,
+                new_key,
+                source_object,
+                metadata,
+                tagging,
+                self.upload_options,
+            )
+            .await?
+        };
+        Ok(match outcome {
+            UploadOutcome::Uploaded => CopyOutcome::Uploaded {
+                multipart,
+                checksum,
+            },
+            UploadOutcome::AlreadyPresent => CopyOutcome::AlreadyPresent,
+        })
+    }
+}
+
+/// The state of a run across its buckets.
+struct Run<'a> {
+    old_client: &'a Client,
+    new_client: &'a Client,
+    options: &'a RunOptions,
+    progress: &'a Progress,
+    started: Instant,
+    regional_new_clients: HashMap<String, Client>,
+    bucket_operation_limiter: BucketOperationLimiter,
+    listing_retries: ListingRetries,
+    failures: Failures,
+    needs_restore_file: Option<File>,
+    plan: Option<Plan>,
+    watermarks: Option<Watermarks>,
+    manifest: Option<Manifest>,
+    state: Option<State>,
+    idle_watchdog: Option<Arc<IdleWatchdog>>,
+    /// Counts kept as they are in the summary; the rest are filled in by `finish`.
+    summary: RunSummary,
+    flagged_keys: usize,
+    needs_restore: usize,
+    dedup_saved_bytes: i64,
+    retention_updated: usize,
+    stale_uploads_aborted: usize,
+    rewritten_metadata_keys: BTreeMap<String, usize>,
+}
+
+impl<'a> Run<'a> {
+    fn new(
+        old_client: &'a Client,
+        new_client: &'a Client,
+        options: &'a RunOptions,
+        progress: &'a Progress,
+    ) -> Self {
+        Run {
+            old_client,
+            new_client,
+            options,
+            progress,
+            started: Instant::now(),
+            regional_new_clients: HashMap::new(),
+            bucket_operation_limiter: BucketOperationLimiter::new(
+                options.bucket_operation_interval,
+            ),
+            listing_retries: ListingRetries::new(
+                options.list_page_attempts,
+                options.skip_unreadable_pages,
+            ),
+            failures: Failures::new(),
+            needs_restore_file: options.needs_restore_file.as_ref().map(|path| {
+                File::create(path).unwrap_or_else(|e| panic!("Failed to create {}: {}", path, e))
+            }),
+            plan: options.dry_run.then(|| {
+                Plan::new(
+                    options.plan_format.clone(),
+                    options.plan_output.as_deref(),
+                    options.plan_to_stderr,
+                )
+            }),
+            watermarks: options.watermark_file.clone().map(Watermarks::load),
+            manifest: options.manifest_file.clone().map(Manifest::open),
+            state: options.state_file.clone().map(State::open),
+            idle_watchdog: options
+                .exit_on_idle
+                .map(|timeout| Arc::new(IdleWatchdog::new(timeout))),
+            summary: RunSummary {
+                dry_run: options.dry_run,
+                ..Default::default()
+            },
+            flagged_keys: 0,
+            needs_restore: 0,
+            dedup_saved_bytes: 0,
+            retention_updated: 0,
+            stale_uploads_aborted: 0,
+            rewritten_metadata_keys: BTreeMap::new(),
+        }
+    }
+
+    /// Within one endpoint and account, objects are copied by the endpoint itself. Options that
+    /// change the object on the way need its data or headers, so they keep the get/put path.
+    fn server_side(&self) -> bool {
+        let options = self.options;
+        options.server_side_copy
+            && !options.upload_options.if_none_match
+            && !options.upload_options.checksums
+            && !options.preserve_ownership_metadata
+            && options.metadata_key_rewrites.is_none()
+    }
+
+    /// Records a bucket that cannot be copied, and stops the run if bucket errors are fatal.
+    fn skip_bucket(&mut self, bucket_name: &str, reason: String) -> ControlFlow<()> {
+        self.summary
+            .skipped_buckets
+            .push((bucket_name.to_string(), reason));
+        if self.options.fail_fast_on_bucket_error {
+            error!("Aborted (bucket error): {}", bucket_name);
+            self.summary.aborted = true;
+            return ControlFlow::Break(());
+        }
+        warn!("Skipped bucket (bucket error): {}", bucket_name);
+        ControlFlow::Continue(())
+    }
+
+    /// Records a bucket whose settings cannot be copied in full, and stops the run for STRICT.
+    fn stop_strict(&mut self, bucket_name: &str, reason: String) -> ControlFlow<()> {
+        error!("Aborted: {}: {} (STRICT)", bucket_name, reason);
+        self.summary
+            .skipped_buckets
+            .push((bucket_name.to_string(), reason));
+        self.summary.aborted = true;
+        self.summary.strict_failure = true;
+        ControlFlow::Break(())
+    }
+
+    /// The source prefixes to copy from the bucket, if not all of it.
+    fn prefixes(&self, bucket: &BucketConfig) -> Option<Vec<String>> {
+        match &bucket.prefix {
+            Some(prefix) => Some(vec![prefix.clone()]),
+            None => self.options.prefixes.clone(),
+        }
+    }
+
+    /// Lists the source objects under the prefixes, or the whole bucket without them, stopping
+    /// once over `max_objects`.
+    async fn list_source(
+        &self,
+        bucket_name: &str,
+        prefixes: Option<&[String]>,
+        shard_concurrency: usize,
+        max_objects: Option<usize>,
+    ) -> Result<Vec<Object>, String> {
+        let request_payer = &self.options.upload_options.request_payer;
+        let Some(prefixes) = prefixes else {
+            if self.options.auto_shard {
+                return list_source_objects_sharded(
+                    self.old_client,
+                    bucket_name,
+                    request_payer.clone(),
+                    shard_concurrency,
+                    &self.listing_retries,
+                    max_objects,
+                )
+                .await;
+            }
+            return list_source_objects(
+                self.old_client,
+                bucket_name,
+                None,
+                request_payer.clone(),
+                &self.listing_retries,
+                max_objects,
+            )
+            .await;
+        };
+        let mut objects = vec![];
+        for prefix in prefixes {
+            if max_objects.is_some_and(|max_objects| objects.len() > max_objects) {
+                break;
+            }
+            let prefix_objects = list_source_objects(
+                self.old_client,
+                bucket_name,
+                Some(prefix),
+                request_payer.clone(),
+                &self.listing_retries,
+                max_objects.map(|max_objects| max_objects - objects.len()),
+            )
+            .await?;
+            info!("Prefix: {} ({} objects)", prefix, prefix_objects.len());
+            objects.extend(prefix_objects);
+        }
+        Ok(objects)
+    }
+
+    /// Lists the destination objects under the prefixes, or the whole bucket without them.
+    async fn list_destination(
+        &self,
+        new_client: &Client,
+        new_bucket_name: &str,
+        prefixes: Option<&[String]>,
+    ) -> Result<Vec<Object>, String> {
+        let request_payer = &self.options.upload_options.request_payer;
+        let Some(prefixes) = prefixes else {
+            return list_destination_objects(
+                new_client,
+                new_bucket_name,
+                None,
+                request_payer.clone(),
+                &self.listing_retries,
+            )
+            .await;
+        };
+        let mut objects = vec![];
+        for prefix in prefixes {
+            objects.extend(
+                list_destination_objects(
+                    new_client,
+                    new_bucket_name,
+                    Some(prefix),
+                    request_payer.clone(),
+                    &self.listing_retries,
+                )
+                .await?,
+            );
+        }
+        Ok(objects)
+    }
+
+    /// With REQUIRE_EMPTY_DESTINATION, lists the destinations of all the buckets before any of
+    /// them is copied. `Break` if one is not empty: each such bucket is recorded with the objects
+    /// found in it and the run is aborted. A destination that does not exist yet is empty.
+    async fn require_empty_destinations(&mut self, buckets: &[&BucketConfig]) -> ControlFlow<()> {
+        let options = self.options;
+        if !options.require_empty_destination {
+            return ControlFlow::Continue(());
+        }
+        for bucket in buckets {
+            let new_bucket_name = bucket.dest();
+            self.bucket_operation_limiter.wait().await;
+            let new_client = match bucket_region(self.new_client, new_bucket_name).await {
+                Ok(region) if region != options.new_region => self
+                    .regional_new_clients
+                    .entry(region.clone())
+                    .or_insert_with(|| regional_client(self.new_client, &region))
+                    .clone(),
+                _ => self.new_client.clone(),
+            };
+            // Destination keys only share the source prefixes when keys are copied unchanged.
+            let prefixes = self
+                .prefixes(bucket)
+                .filter(|_| options.key_rules.is_identity());
+            let mut found = match self
+                .list_destination(&new_client, new_bucket_name, prefixes.as_deref())
+                .await
+            {
+                Ok(objects) => objects
+                    .into_iter()
+                    .map(|object| object.key.unwrap())
+                    .collect::<Vec<String>>(),
+                // A destination that cannot be listed cannot be shown to be empty.
+                Err(e) => {
+                    error!("{} could not be listed: {}", new_bucket_name, e);
+                    self.summary.skipped_buckets.push((
+                        bucket.source.clone(),
+                        format!("destination {} could not be listed: {}", new_bucket_name, e),
+                    ));
+                    self.summary.aborted = true;
+                    continue;
+                }
+            };
+            if !found.is_empty() {
+                found.sort_unstable();
+                error!(
+                    "{} is not empty ({} objects, e.g. {})",
+                    new_bucket_name,
+                    found.len(),
+                    found
+                        .iter()
+                        .take(10)
+                        .cloned()
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+                self.summary.skipped_buckets.push((
+                    bucket.source.clone(),
+                    format!(
+                        "destination {} is not empty ({} objects)",
+                        new_bucket_name,
+                        found.len()
+                    ),
+                ));
+                self.summary.aborted = true;
+            }
+        }
+        if self.summary.aborted {
+            return ControlFlow::Break(());
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Copies the buckets in order until one of them ends the run, the run is cancelled or the
+    /// idle watchdog fires.
+    async fn copy_buckets(&mut self, buckets: &[&BucketConfig]) {
+        let cancel = self.options.cancel.clone();
+        let idle_watchdog = self.idle_watchdog.clone();
+        let copy = async {
+            for bucket in buckets {
+                let span = info_span!("bucket", bucket = bucket.source.as_str());
+                if self.copy_bucket(bucket).instrument(span).await.is_break() {
+                    break;
+                }
+            }
+        };
+        let cancelled = async {
+            match &cancel {
+                Some(cancel) => cancel.notified().await,
+                None => future::pending().await,
+            }
+        };
+        let idle = async {
+            match &idle_watchdog {
+                Some(idle_watchdog) => idle_watchdog.idle().await,
+                None => future::pending().await,
+            }
+        };
+        let stopped = tokio::select! {
+            biased;
+            _ = cancelled => Some("Cancelled".to_string()),
+            _ = idle => Some(format!(
+                "Idle watchdog fired: no object completed in {}s",
+                idle_watchdog.as_ref().unwrap().timeout.as_secs()
+            )),
+            _ = copy => None,
+        };
+        if let Some(reason) = stopped {
+            error!("{}, the copies in flight were stopped", reason);
+            self.summary.aborted = true;
+            let upload_options = &self.options.upload_options;
+            upload_options.open_uploads.abort_all(upload_options).await;
+        }
+    }
+
+    /// Copies one bucket. `Break` ends the run, after a fatal bucket error or once the byte
+    /// budget is used up.
+    async fn copy_bucket(&mut self, bucket: &BucketConfig) -> ControlFlow<()> {
+        let options = self.options;
+        let (old_client, progress) = (self.old_client, self.progress);
+        let dry_run = options.dry_run;
+        let request_payer = options.upload_options.request_payer.clone();
+        let bucket_name = bucket.source.as_str();
+        info!("Bucket: {}", bucket_name);
+        let failures_before = self.failures.objects.len();
+        let unlisted_before = self.listing_retries.unlisted_count();
+
+        let mut new_bucket_name = bucket.dest().to_string();
+        let prefixes = self.prefixes(bucket);
+        let object_concurrency = bucket
+            .object_concurrency
+            .unwrap_or(options.object_concurrency);
+        let upload_options = match bucket.storage_class() {
+            Some(storage_class) => UploadOptions {
+                storage_class_rules: vec![StorageClassRule::any_size(storage_class)],
+                ..options.upload_options.clone()
+            },
+            None => options.upload_options.clone(),
+        };
+
+        // The cap is checked as the source is listed, before anything is created or copied.
+        let max_objects = options
+            .max_object_count
+            .filter(|_| !options.max_object_count_warn_only);
+        // A listing that fails after its retries is a bucket-level failure, like a failed
+        // bucket creation.
+        let mut objects = match self
+            .list_source(
+                bucket_name,
+                prefixes.as_deref(),
+                object_concurrency,
+                max_objects,
+            )
+            .await
+        {
+            Ok(objects) => objects,
+            Err(e) => return self.skip_bucket(bucket_name, format!("listing failed: {}", e)),
+        };
+        if let Some(max_object_count) = options.max_object_count {
+            if objects.len() > max_object_count {
+                if options.max_object_count_warn_only {
+                    warn!(
+                        "{} has {} objects, more than MAX_OBJECT_COUNT_PER_BUCKET ({})",
+                        bucket_name,
+                        objects.len(),
+                        max_object_count
+                    );
+                } else {
+                    error!(
+                        "{} has more than MAX_OBJECT_COUNT_PER_BUCKET ({}) objects, aborting",
+                        bucket_name, max_object_count
+                    );
+                    self.summary.skipped_buckets.push((
+                        bucket_name.to_string(),
+                        format!(
+                            "more than MAX_OBJECT_COUNT_PER_BUCKET ({}) objects",
+                            max_object_count
+                        ),
+                    ));
+                    self.summary.aborted = true;
+                    return ControlFlow::Break(());
+                }
+            }
+        }
+        let newest_object = self
+            .watermarks
+            .as_ref()
+            .and_then(|watermarks| watermarks.filter(bucket_name, &mut objects));
+
+        // Bucket-level failures skip the bucket (or end the run with FAIL_FAST_ON_BUCKET_ERROR),
+        // while a failed object is recorded and the next one copied (unless STRICT is set).
+        let mut bucket_error = None;
+        // Retention is only applied to objects already at the destination, so a missing bucket
+        // is skipped rather than created.
+        if !dry_run && !options.retention_only {
+            self.bucket_operation_limiter.wait().await;
+            if let Err(e) = self
+                .new_client
+                .create_bucket()
+                .bucket(&new_bucket_name)
+                .send()
+                .await
+            {
+                let e = format!("{:?}", e);
+                if e.contains("BucketAlreadyExists") {
+                    new_bucket_name += options.bucket_suffix.as_deref().expect(
+                        "--bucket-suffix (NEW_BUCKET_SUFFIX) must be set to avoid conflicts with existing buckets",
+                    );
+                    self.bucket_operation_limiter.wait().await;
+                    let _ = self
+                        .new_client
+                        .create_bucket()
+                        .bucket(&new_bucket_name)
+                        .send()
+                        .await;
+                } else if !e.contains("BucketAlreadyOwnedByYou") {
+                    bucket_error = Some(format!("bucket creation failed: {}", e));
+                }
+            }
+        }
+
+        let new_bucket_region = match bucket_error {
+            Some(e) => Err(e),
+            None => {
+                info!("New Bucket: {}", new_bucket_name);
+                self.bucket_operation_limiter.wait().await;
+                match bucket_region(self.new_client, &new_bucket_name).await {
+                    Ok(region) => Ok(region),
+                    // In a dry run the bucket may not have been created yet.
+                    Err(e) if options.region_fallback || dry_run => {
+                        warn!(
+                            "Region detection failed, using {}: {}: {}",
+                            options.new_region, new_bucket_name, e
+                        );
+                        Ok(options.new_region.clone())
+                    }
+                    Err(e) => Err(format!("region detection failed: {}", e)),
+                }
+            }
+        };
+        let new_bucket_region = match new_bucket_region {
+            Ok(region) => region,
+            Err(e) => return self.skip_bucket(bucket_name, e),
+        };
+        let new_client = if new_bucket_region == options.new_region {
+            self.new_client.clone()
+        } else {
+            info!("New Bucket Region: {}", new_bucket_region);
+            self.regional_new_clients
+                .entry(new_bucket_region.clone())
+                .or_insert_with(|| regional_client(self.new_client, &new_bucket_region))
+                .clone()
+        };
+
+        if options.copy_bucket_notifications && !dry_run {
+            self.bucket_operation_limiter.wait().await;
+            if let Err(e) = copy_notifications(
+                old_client,
+                &new_client,
+                bucket_name,
+                &new_bucket_name,
+                &options.notification_arn_map,
+                options.strict,
+            )
+            .await
+            {
+                let reason = format!("notification copy failed: {}", e);
+                if options.strict {
+                    return self.stop_strict(bucket_name, reason);
+                }
+                return self.skip_bucket(bucket_name, reason);
+            }
+            self.bucket_operation_limiter.wait().await;
+            warn_replication(old_client, bucket_name).await;
+        }
+        self.summary.buckets += 1;
+
+        if let Some(older_than) = options.abort_stale_uploads_older_than {
+            self.stale_uploads_aborted += abort_stale_uploads(
+                &new_client,
+                &new_bucket_name,
+                older_than,
+                dry_run,
+                request_payer.clone(),
+            )
+            .await;
+        }
+
+        // Destination keys only share the source prefixes when keys are copied unchanged.
+        let destination_objects = match self
+            .list_destination(
+                &new_client,
+                &new_bucket_name,
+                prefixes
+                    .as_deref()
+                    .filter(|_| options.key_rules.is_identity()),
+            )
+            .await
+        {
+            Ok(objects) => objects,
+            Err(e) => {
+                return self.skip_bucket(bucket_name, format!("destination listing failed: {}", e))
+            }
+        };
+        let mut migrated_objects = destination_objects
+            .iter()
+            .map(|object| object.key.clone().unwrap())
+            .collect::<Vec<String>>();
+        migrated_objects.sort_unstable();
+
+        objects.retain(|object| {
+            let object_key = object.key.as_deref().unwrap();
+            if options.deny_keys.contains(object_key) {
+                info!("Denied: {}", object_key);
+                if let Some(plan) = self.plan.as_mut() {
+                    plan.record(
+                        PlanAction::Skip,
+                        (bucket_name, object_key),
+                        (
+                            &new_bucket_name,
+                            &options.key_rules.destination_key(bucket_name, object),
+                        ),
+                        object.size.unwrap_or_default(),
+                        Some("denied"),
+                    );
+                }
+                false
+            } else {
+                true
+            }
+        });
+        if let (Some(path), Some(file)) = (
+            &options.needs_restore_file,
+            self.needs_restore_file.as_mut(),
+        ) {
+            // Archived objects cannot be read until restored, so they are listed for a later run
+            // instead of failing one by one.
+            objects.retain(|object| {
+                if !matches!(
+                    object.storage_class,
+                    Some(ObjectStorageClass::Glacier | ObjectStorageClass::DeepArchive)
+                ) {
+                    return true;
+                }
+                let object_key = object.key.as_deref().unwrap();
+                info!("Needs restore: {}", object_key);
+                writeln!(file, "{}\t{}", bucket_name, object_key)
+                    .unwrap_or_else(|e| panic!("Failed to write {}: {}", path, e));
+                self.needs_restore += 1;
+                false
+            });
+        }
+        if let Some(key_validation) = &options.key_validation {
+            objects.retain(|object| {
+                let object_key = object.key.as_deref().unwrap();
+                let problems = options
+                    .key_checks
+                    .iter()
+                    .filter(|check| check.matches(object_key))
+                    .map(KeyCheck::name)
+                    .collect::<Vec<&str>>();
+                if problems.is_empty() {
+                    return true;
+                }
+                self.flagged_keys += 1;
+                info!(
+                    "Problematic key: {:?} ({})",
+                    object_key,
+                    problems.join(", ")
+                );
+                !matches!(key_validation, KeyValidation::Skip)
+            });
+        }
+        let mut objects = objects
+            .into_iter()
+            .map(|object| {
+                let new_object_key = options.key_rules.destination_key(bucket_name, &object);
+                (object, new_object_key)
+            })
+            .collect::<Vec<(Object, String)>>();
+
+        if !options.key_rules.is_identity() {
+            let mut sources_by_destination: HashMap<&str, Vec<&str>> = HashMap::new();
+            for (object, new_object_key) in &objects {
+                sources_by_destination
+                    .entry(new_object_key)
+                    .or_default()
+                    .push(object.key.as_deref().unwrap());
+            }
+            for (new_object_key, object_keys) in sources_by_destination {
+                if object_keys.len() > 1 {
+                    warn!("{} all map to {}", object_keys.join(", "), new_object_key);
+                }
+            }
+        }
+
+        if let Some(case_collisions) = &options.case_collisions {
+            let mut keys_by_lowercase: HashMap<String, HashSet<&str>> = HashMap::new();
+            for new_object_key in objects
+                .iter()
+                .map(|(_, new_object_key)| new_object_key)
+                .chain(&migrated_objects)
+            {
+                keys_by_lowercase
+                    .entry(new_object_key.to_lowercase())
+                    .or_default()
+                    .insert(new_object_key);
+            }
+            let mut collisions = keys_by_lowercase
+                .into_values()
+                .filter(|keys| keys.len() > 1)
+                .map(|keys| {
+                    let mut keys = keys.into_iter().collect::<Vec<&str>>();
+                    keys.sort_unstable();
+                    keys.join(", ")
+                })
+                .collect::<Vec<String>>();
+            collisions.sort_unstable();
+            for collision in &collisions {
+                info!("Case collision: {}", collision);
+            }
+            if !collisions.is_empty() && matches!(case_collisions, CaseCollisions::Abort) {
+                error!(
+                    "Aborted: {} has {} case-insensitive key collisions",
+                    new_bucket_name,
+                    collisions.len()
+                );
+                self.summary.skipped_buckets.push((
+                    bucket_name.to_string(),
+                    format!("case-insensitive key collisions: {}", collisions.join("; ")),
+                ));
+                self.summary.aborted = true;
+                return ControlFlow::Break(());
+            }
+        }
+
+        if options.retention_only {
+            let (new_client, new_bucket_name) = (&new_client, new_bucket_name.as_str());
+            let upload_options = &upload_options;
+            let mut objects = objects.iter().filter(|(_, new_object_key)| {
+                migrated_objects.binary_search(new_object_key).is_ok()
+            });
+            let mut in_flight = FuturesUnordered::new();
+            loop {
+                while in_flight.len() < object_concurrency && !self.summary.stopped_by_failures() {
+                    let Some((object, new_object_key)) = objects.next() else {
+                        break;
+                    };
+                    if dry_run {
+                        info!("Retention (dry run): {}", new_object_key);
+                        continue;
+                    }
+                    let object_key = object.key.as_deref().unwrap();
+                    in_flight.push(async move {
+                        let outcome = copy_retention(
+                            old_client,
+                            new_client,
+                            bucket_name,
+                            object_key,
+                            new_bucket_name,
+                            new_object_key,
+                            upload_options,
+                        )
+                        .await;
+                        (object_key, new_object_key, outcome)
+                    });
+                }
+                let Some((object_key, new_object_key, outcome)) = in_flight.next().await else {
+                    break;
+                };
+                match outcome {
+                    Ok(updated) => {
+                        self.failures.succeeded();
+                        if updated {
+                            info!("Retention: {}", new_object_key);
+                            self.retention_updated += 1;
+                        }
+                    }
+                    Err(e) => self.failures.record(bucket_name, object_key, e),
+                }
+                self.failures.stop(options, &mut self.summary);
+            }
+            if self.summary.stopped_by_failures() {
+                return ControlFlow::Break(());
+            }
+            return ControlFlow::Continue(());
+        }
+
+        let destination_by_key = destination_objects
+            .iter()
+            .map(|object| (object.key.as_deref().unwrap(), object))
+            .collect::<HashMap<&str, &Object>>();
+        let planned_objects = objects.len();
+        let mut overwritten_keys = HashSet::new();
+        objects.retain(|(object, new_object_key)| {
+            let destination = destination_by_key.get(new_object_key.as_str());
+            if destination.is_some_and(|destination| is_same_content(object, destination))
+                || self.state.as_ref().is_some_and(|state| {
+                    state.is_finished(&new_bucket_name, new_object_key, object)
+                })
+            {
+                if let Some(plan) = self.plan.as_mut() {
+                    plan.record(
+                        PlanAction::Skip,
+                        (bucket_name, object.key.as_deref().unwrap()),
+                        (&new_bucket_name, new_object_key),
+                        object.size.unwrap_or_default(),
+                        None,
+                    );
+                }
+                return false;
+            }
+            if destination.is_some() {
+                info!("Differs from destination: {}", new_object_key);
+                overwritten_keys.insert(new_object_key.clone());
+            }
+            true
+        });
+        self.summary.objects_skipped += planned_objects - objects.len();
+        sort_objects(&mut objects, &options.object_order);
+
+        let constraint = BucketLocationConstraint::from(options.new_region.as_str());
+        let bucket_config = CreateBucketConfiguration::builder()
+            .location_constraint(constraint)
+            .build();
+        if !dry_run {
+            self.bucket_operation_limiter.wait().await;
+            let _ = new_client
+                .create_bucket()
+                .create_bucket_configuration(bucket_config)
+                .bucket(&new_bucket_name)
+                .send()
+                .await;
+        }
+
+        let mut copied_contents: HashMap<(String, i64), String> = HashMap::new();
+        if options.dedup_destination {
+            for object in destination_objects {
+                if let (Some(key), Some(content)) = (object.key, object.e_tag.zip(object.size)) {
+                    copied_contents.entry(content).or_insert(key);
+                }
+            }
+        }
+        let mut remaining_bytes: i64 = objects
+            .iter()
+            .map(|(object, _)| object.size.unwrap_or_default())
+            .sum();
+        progress.bucket(bucket_name, objects.len(), remaining_bytes);
+
+        let dedup = options.dedup || options.dedup_destination;
+        let copy = BucketCopy {
+            old_client,
+            new_client: &new_client,
+            bucket: bucket_name,
+            new_bucket: &new_bucket_name,
+            options,
+            upload_options: &upload_options,
+            server_side: self.server_side(),
+            stored_checksum: self.manifest.is_some(),
+        };
+        let copy = &copy;
+        let mut objects = objects.into_iter();
+        let mut in_flight = FuturesUnordered::new();
+        let mut budget_reached = false;
+        loop {
+            while in_flight.len() < object_concurrency {
+                if let Some(idle_watchdog) = &self.idle_watchdog {
+                    idle_watchdog.progress();
+                }
+                if let Some(pause_file) = &options.pause_file {
+                    // Objects in flight are only polled while this loop waits for them, so they
+                    // are finished before pausing.
+                    if !in_flight.is_empty() && Path::new(pause_file).exists() {
+                        break;
+                    }
+                    wait_while_paused(pause_file, self.idle_watchdog.as_deref()).await;
+                }
+                if self.summary.stopped_by_failures() {
+                    break;
+                }
+                if options
+                    .max_bytes
+                    .is_some_and(|max_bytes| self.summary.bytes >= max_bytes)
+                {
+                    if !budget_reached {
+                        info!(
+                            "Byte budget reached, {} bytes left in {}",
+                            remaining_bytes, bucket_name
+                        );
+                    }
+                    budget_reached = true;
+                    break;
+                }
+                let Some((object, new_object_key)) = objects.next() else {
+                    break;
+                };
+                let object_size = object.size.unwrap_or_default();
+                remaining_bytes -= object_size;
+
+                let object_key = object.key.as_deref().unwrap();
+                if new_object_key == object_key {
+                    info!(key = object_key, size = object_size, "Object");
+                } else {
+                    info!(
+                        key = object_key,
+                        new_key = %new_object_key,
+                        size = object_size,
+                        "Object"
+                    );
+                }
+                progress.started(object_key, object_size);
+
+                if dry_run {
+                    let multipart = upload_options.is_multipart(object.size);
+                    info!(
+                        key = object_key,
+                        size = object_size,
+                        mode = if multipart { "multipart" } else { "singlepart" },
+                        "Would copy"
+                    );
+                    if let Some(plan) = self.plan.as_mut() {
+                        plan.record(
+                            if overwritten_keys.contains(&new_object_key) {
+                                PlanAction::Overwrite
+                            } else {
+                                PlanAction::Copy
+                            },
+                            (bucket_name, object_key),
+                            (&new_bucket_name, &new_object_key),
+                            object_size,
+                            None,
+                        );
+                    }
+                    self.summary.bytes += object_size;
+                    self.summary.objects_copied += 1;
+                    if multipart {
+                        self.summary.multipart_objects += 1;
+                    } else {
+                        self.summary.singlepart_objects += 1;
+                    }
+                    progress.finished(object_key, object_size);
+                    continue;
+                }
+
+                let metadata = options
+                    .preserve_ownership_metadata
+                    .then(|| object.owner.as_ref().and_then(|owner| owner.id.clone()))
+                    .flatten()
+                    .map(|owner| HashMap::from([("original-owner".to_string(), owner)]));
+
+                let source_storage_class = object
+                    .storage_class
+                    .as_ref()
+                    .map(ObjectStorageClass::as_str);
+                if let (Some(map), Some(source)) =
+                    (&upload_options.storage_class_map, source_storage_class)
+                {
+                    if !map.contains(source) {
+                        *self
+                            .summary
+                            .unmapped_storage_classes
+                            .entry(source.to_string())
+                            .or_default() += 1;
+                        if options.strict {
+                            let e = format!(
+                                "Storage class {} has no mapping in STORAGE_CLASS_MAP",
+                                source
+                            );
+                            progress.failed(object_key, &e);
+                            self.failures.record(bucket_name, object_key, e);
+                            self.failures.stop(options, &mut self.summary);
+                            continue;
+                        }
+                    }
+                }
+                let storage_class = upload_options.storage_class(object_size, source_storage_class);
+                if let Some(storage_class) = &storage_class {
+                    *self
+                        .summary
+                        .storage_classes
+                        .entry(storage_class.as_str().to_string())
+                        .or_default() += 1;
+                }
+
+                // Only objects whose copy has finished are in the set, so a duplicate of an
+                // object still in flight is uploaded in full. So is a duplicate over the 5GiB
+                // limit of `CopyObject`, or with IF_NONE_MATCH, which `CopyObject` cannot send.
+                let copied_key = object
+                    .e_tag
+                    .clone()
+                    .zip(object.size)
+                    .filter(|_| {
+                        dedup
+                            && object_size as usize <= MAX_SINGLEPART_SIZE
+                            && !upload_options.if_none_match
+                    })
+                    .and_then(|content| copied_contents.get(&content).cloned());
+                if let Some(copied_key) = &copied_key {
+                    info!("Duplicate of: {}", copied_key);
+                }
+
+                let span = info_span!(
+                    "object",
+                    key = object.key.as_deref().unwrap(),
+                    destination_key = new_object_key.as_str(),
+                    size = object_size,
+                );
+                in_flight.push(
+                    async move {
+                        let mut rewritten_keys = vec![];
+                        let outcome = copy
+                            .copy_object(
+                                &object,
+                                &new_object_key,
+                                copied_key,
+                                metadata,
+                                storage_class,
+                                &mut rewritten_keys,
+                            )
+                            .await;
+                        (object, new_object_key, rewritten_keys, outcome)
+                    }
+                    .instrument(span),
+                );
+            }
+
+            let Some((object, new_object_key, rewritten_keys, outcome)) = in_flight.next().await
+            else {
+                break;
+            };
+            if let Some(idle_watchdog) = &self.idle_watchdog {
+                idle_watchdog.progress();
+            }
+            for old in rewritten_keys {
+                *self.rewritten_metadata_keys.entry(old).or_default() += 1;
+            }
+            let object_key = object.key.as_deref().unwrap();
+            let object_size = object.size.unwrap_or_default();
+            if let Some(state) = self.state.as_mut().filter(|_| {
+                matches!(
+                    outcome,
+                    Ok(CopyOutcome::Duplicate | CopyOutcome::Uploaded { .. })
+                )
+            }) {
+                state.record(&new_bucket_name, &new_object_key, &object);
+            }
+            if outcome.is_ok() {
+                self.failures.succeeded();
+            }
+            match outcome {
+                Ok(CopyOutcome::Duplicate) => {
+                    progress.finished(object_key, 0);
+                    self.dedup_saved_bytes += object_size;
+                    self.summary.objects_copied += 1;
+                    if let Some(manifest) = self.manifest.as_mut() {
+                        manifest.record(&new_bucket_name, &new_object_key, &object, None);
+                    }
+                }
+                Ok(CopyOutcome::Uploaded {
+                    multipart,
+                    checksum,
+                }) => {
+                    progress.finished(object_key, object_size);
+                    self.summary.bytes += object_size;
+                    self.summary.objects_copied += 1;
+                    if multipart {
+                        self.summary.multipart_objects += 1;
+                    } else {
+                        self.summary.singlepart_objects += 1;
+                    }
+                    if let Some(manifest) = self.manifest.as_mut() {
+                        manifest.record(
+                            &new_bucket_name,
+                            &new_object_key,
+                            &object,
+                            checksum.as_ref(),
+                        );
+                    }
+                    if dedup {
+                        if let Some(content) = object.e_tag.clone().zip(object.size) {
+                            copied_contents.insert(content, new_object_key);
+                        }
+                    }
+                }
+                Ok(CopyOutcome::AlreadyPresent) => {
+                    progress.finished(object_key, 0);
+                    info!("Skipped (already present): {}", new_object_key);
+                    self.summary.objects_skipped += 1;
+                }
+                Err(e) => {
+                    progress.failed(object_key, &e);
+                    self.failures.record(bucket_name, object_key, e);
+                }
+            }
+            self.failures.stop(options, &mut self.summary);
+        }
+        if let Some(idle_watchdog) = &self.idle_watchdog {
+            idle_watchdog.stop();
+        }
+        if budget_reached || self.summary.stopped_by_failures() {
+            return ControlFlow::Break(());
+        }
+
+        // Failed objects are older than the new watermark, so it is only advanced when every
+        // object of the bucket was listed and copied.
+        if let Some((watermarks, newest_object)) = self
+            .watermarks
+            .as_mut()
+            .filter(|_| {
+                !dry_run
+                    && self.failures.objects.len() == failures_before
+                    && self.listing_retries.unlisted_count() == unlisted_before
+            })
+            .zip(newest_object)
+        {
+            watermarks.advance(bucket_name, newest_object);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn finish(self) -> RunSummary {
+        let options = self.options;
+        RunSummary {
+            failed_objects: self.failures.objects,
+            dedup_saved_bytes: (options.dedup || options.dedup_destination)
+                .then_some(self.dedup_saved_bytes),
+            problematic_keys: options
+                .key_validation
+                .is_some()
+                .then_some(self.flagged_keys),
+            needs_restore: self
+                .needs_restore_file
+                .is_some()
+                .then_some(self.needs_restore),
+            unlisted_ranges: self.listing_retries.into_unlisted(),
+            retention_updated: options.retention_only.then_some(self.retention_updated),
+            stale_uploads_aborted: options
+                .abort_stale_uploads_older_than
+                .is_some()
+                .then_some(self.stale_uploads_aborted),
+            rewritten_metadata_keys: self
+                .rewritten_metadata_keys
+                .into_iter()
+                .map(|(old, objects)| {
+                    let new = options.metadata_key_rewrites.as_ref().unwrap()[&old].clone();
+                    (old, new, objects)
+                })
+                .collect(),
+            duration: self.started.elapsed(),
+            ..self.summary
+        }
+    }
+}
+
+/// Copies the buckets of `options` (every bucket of the source when unset) from `old_client`
+/// to `new_client`. Objects that cannot be copied are counted in the summary instead of
+/// stopping the run, unless `strict` is set, in which case the run ends after the first one.
+pub async fn run(
+    old_client: &Client,
+    new_client: &Client,
+    options: &RunOptions,
+    progress: &Progress,
+) -> RunSummary {
+    if let Some(path) = &options.presigned_urls {
+        return copy_presigned(new_client, path, options).await;
+    }
+
+    let listed_buckets;
+    let buckets = match &options.buckets {
+        Some(buckets) => buckets,
+        None => {
+            listed_buckets = old_client
+                .list_buckets()
+                .send()
+                .await
+                .unwrap()
+                .buckets
+                .unwrap_or_default()
+                .into_iter()
+                .map(|bucket| BucketConfig::listed(bucket.name.unwrap()))
+                .collect::<Vec<BucketConfig>>();
+            &listed_buckets
+        }
+    };
+    if buckets.is_empty() {
+        info!("No buckets found");
+    }
+    let buckets = buckets.iter().filter(|bucket| {
+        let name = bucket.source.as_str();
+        options
+            .include_buckets
+            .as_ref()
+            .is_none_or(|patterns| patterns.iter().any(|pattern| matches_glob(pattern, name)))
+            && !options
+                .exclude_buckets
+                .iter()
+                .any(|pattern| matches_glob(pattern, name))
+    });
+    let buckets = buckets.collect::<Vec<&BucketConfig>>();
+
+    let mut run = Run::new(old_client, new_client, options, progress);
+    if run.require_empty_destinations(&buckets).await.is_continue() {
+        run.copy_buckets(&buckets).await;
+    }
+    run.finish()
+}
+
+/// Copies one bucket the way `run` copies each of its buckets.
+pub async fn copy_bucket(
+    old_client: &Client,
+    new_client: &Client,
+    bucket: &BucketConfig,
+    options: &RunOptions,
+    progress: &Progress,
+) -> RunSummary {
+    let mut run = Run::new(old_client, new_client, options, progress);
+    if run
+        .require_empty_destinations(&[bucket])
+        .await
+        .is_continue()
+    {
+        run.copy_buckets(&[bucket]).await;
+    }
+    run.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use aws_sdk_s3::types::ObjectCannedAcl;
+
+    use super::*;
+    use crate::{
+        fake_s3::{FakeS3, StoredObject, OWNER},
+        upload::{RequestLimit, StorageClassMap},
+    };
+
+    fn test_options() -> RunOptions {
+        RunOptions {
+            bucket_operation_interval: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    fn stored_object(content: &[u8], headers: &[(&str, &str)]) -> StoredObject {
+        StoredObject {
+            content: content.to_vec(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Copies one bucket in key order, uploading objects over 8 bytes in 4-byte parts and
+    /// copying duplicates within the destination.
+    async fn copy_with_every_path(old: &FakeS3, new: &FakeS3, options: RunOptions) -> RunSummary {
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                dedup: true,
+                object_concurrency: 1,
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    ..Default::default()
+                },
+                ..options
+            },
+            &Progress::default(),
+        )
+        .await;
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(summary.multipart_objects, 1);
+        assert_eq!(summary.dedup_saved_bytes, Some(4));
+        summary
+    }
+
+    #[tokio::test]
+    async fn copies_keep_the_content_type_of_their_source() {
+        let old = FakeS3::default()
+            .with_stored_object(
+                "site",
+                "a/logo.png",
+                stored_object(b"logo", &[("content-type", "image/png")]),
+            )
+            .with_stored_object(
+                "site",
+                "b/logo.txt",
+                stored_object(b"logo", &[("content-type", "text/plain")]),
+            )
+            .with_stored_object(
+                "site",
+                "c/photo.jpg",
+                stored_object(b"large photo", &[("content-type", "image/jpeg")]),
+            );
+        let new = FakeS3::default();
+
+        copy_with_every_path(&old, &new, test_options()).await;
+
+        for (key, content_type) in [
+            ("a/logo.png", "image/png"),
+            ("b/logo.txt", "text/plain"),
+            ("c/photo.jpg", "image/jpeg"),
+        ] {
+            let object = new.stored_object("site", key).unwrap();
+            assert_eq!(object.content, old.object("site", key).unwrap());
+            assert_eq!(object.headers["content-type"], content_type, "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn parts_without_an_etag_fail_the_object_instead_of_completing_it() {
+        let old = FakeS3::default()
+            .with_object("videos", "clip.mp4", b"a long video clip")
+            .with_object("videos", "poster.jpg", b"poster");
+        let new = FakeS3::default().without_part_e_tags();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    part_attempts: 1,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert_eq!(summary.objects_copied, 1);
+        assert_eq!(summary.failed_objects.len(), 1);
+        assert_eq!(summary.failed_objects[0].0, "videos/clip.mp4");
+        assert!(summary.failed_objects[0].1.contains("without an ETag"));
+        assert_eq!(new.object("videos", "clip.mp4"), None);
+        assert_eq!(new.pending_uploads(), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_parts_abort_their_multipart_upload() {
+        let old = FakeS3::default().with_object("videos", "clip.mp4", b"a long video clip");
+        let new = FakeS3::default().fail_next_write("videos", "clip.mp4");
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    part_attempts: 1,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert_eq!(summary.failed_objects.len(), 1);
+        assert_eq!(summary.failed_objects[0].0, "videos/clip.mp4");
+        let requests = new.requests();
+        let aborts = requests
+            .iter()
+            .filter(|request| request.starts_with("DELETE /videos/clip.mp4?"))
+            .collect::<Vec<&String>>();
+        assert_eq!(aborts.len(), 1);
+        assert!(aborts[0].contains("uploadId=upload-1"), "{}", aborts[0]);
+        assert_eq!(new.object("videos", "clip.mp4"), None);
+        assert_eq!(new.pending_uploads(), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_parts_are_retried_until_they_are_uploaded() {
+        let old = FakeS3::default().with_object("videos", "clip.mp4", b"a long video clip");
+        let new = FakeS3::default().fail_part("videos", "clip.mp4", 2, 2);
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    part_attempts: 3,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(
+            summary.failed_objects.is_empty(),
+            "{:?}",
+            summary.failed_objects
+        );
+        assert_eq!(summary.objects_copied, 1);
+        assert_eq!(
+            new.object("videos", "clip.mp4"),
+            Some(b"a long video clip".to_vec())
+        );
+        let part_uploads = |part_number: i32| {
+            let request = format!(
+                "PUT /videos/clip.mp4?x-id=UploadPart&partNumber={}&",
+                part_number
+            );
+            new.requests()
+                .iter()
+                .filter(|sent| sent.starts_with(&request))
+                .count()
+        };
+        assert_eq!(part_uploads(1), 1);
+        assert_eq!(part_uploads(2), 3);
+        assert_eq!(new.pending_uploads(), 0);
+    }
+
+    #[tokio::test]
+    async fn objects_are_written_in_the_destination_bucket_region() {
+        let old = FakeS3::default()
+            .with_object("photos", "cat.jpg", b"meow")
+            .with_object("photos", "dog.jpg", b"a long bark");
+        let new = FakeS3::default().with_bucket_region("photos", "eu-west-1");
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(summary.objects_copied, 2);
+        assert_eq!(
+            new.object("photos", "cat.jpg"),
+            old.object("photos", "cat.jpg")
+        );
+        assert_eq!(
+            new.object("photos", "dog.jpg"),
+            old.object("photos", "dog.jpg")
+        );
+    }
+
+    #[tokio::test]
+    async fn object_acl_is_set_on_every_upload_path() {
+        let old = FakeS3::default()
+            .with_object("site", "a/logo.png", b"logo")
+            .with_object("site", "b/logo.png", b"logo")
+            .with_object("site", "c/photo.jpg", b"large photo");
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                dedup: true,
+                object_concurrency: 1,
+                upload_options: UploadOptions {
+                    acl: Some(ObjectCannedAcl::Private),
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(summary.multipart_objects, 1);
+        assert_eq!(summary.dedup_saved_bytes, Some(4));
+        for key in ["a/logo.png", "b/logo.png", "c/photo.jpg"] {
+            let object = new.stored_object("site", key).unwrap();
+            assert_eq!(object.acl.as_deref(), Some("private"), "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_writes_are_retried_with_the_buffered_body() {
+        let old = FakeS3::default()
+            .with_object("photos", "cat.jpg", b"meow")
+            .with_object("photos", "dog.jpg", b"a long bark");
+        let new = FakeS3::default()
+            .fail_next_write("photos", "cat.jpg")
+            .fail_next_write("photos", "dog.jpg");
+
+        let summary = run(
+            &old.client(),
+            &new.retrying_client(),
+            &RunOptions {
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(summary.singlepart_objects, 1);
+        assert_eq!(summary.multipart_objects, 1);
+        assert_eq!(
+            new.object("photos", "cat.jpg"),
+            old.object("photos", "cat.jpg")
+        );
+        assert_eq!(
+            new.object("photos", "dog.jpg"),
+            old.object("photos", "dog.jpg")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn parts_in_flight_never_exceed_the_part_concurrency() {
+        let old = FakeS3::default().with_object("videos", "clip.mp4", &[7; 64]);
+        let new = FakeS3::default().with_part_delay(Duration::from_millis(20));
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    max_concurrent_parts: 3,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(new.object("videos", "clip.mp4"), Some(vec![7; 64]));
+        let peak = new.peak_parts_in_flight();
+        assert!((2..=3).contains(&peak), "{} parts in flight", peak);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn a_killed_run_resumes_after_the_recorded_objects() {
+        let path = env::temp_dir().join(format!("s3copy-resume-state-{}", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+        let old = (0..6).fold(FakeS3::default(), |fake, i| {
+            fake.with_object("videos", &format!("clip{}.mp4", i), b"a video clip")
+        });
+        let options = || RunOptions {
+            state_file: Some(path.clone()),
+            object_concurrency: 1,
+            upload_options: UploadOptions {
+                multipart_threshold: 8,
+                part_size: 4,
+                max_concurrent_parts: 1,
+                ..Default::default()
+            },
+            ..test_options()
+        };
+
+        // Dropping the run stops it at its next await, like a kill.
+        let (old_client, killed_client, killed_options, progress) = (
+            old.client(),
+            FakeS3::default()
+                .with_part_delay(Duration::from_millis(20))
+                .client(),
+            options(),
+            Progress::default(),
+        );
+        tokio::select! {
+            _ = run(&old_client, &killed_client, &killed_options, &progress) => {
+                panic!("The run finished before it was killed");
+            }
+            _ = async {
+                while fs::read_to_string(&path).map_or(0, |state| state.lines().count()) < 2 {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            } => {}
+        }
+        let recorded = fs::read_to_string(&path).unwrap();
+        let recorded_keys = recorded
+            .lines()
+            .map(|line| {
+                let entry = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                entry["key"].as_str().unwrap().to_string()
+            })
+            .collect::<Vec<String>>();
+        assert!((2..6).contains(&recorded_keys.len()), "{:?}", recorded_keys);
+        // A write cut off by the kill.
+        fs::write(&path, format!("{}{{\"bucket\":\"vid", recorded)).unwrap();
+
+        // The destination is new, so only the state can tell which objects were copied.
+        let new = FakeS3::default();
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &options(),
+            &Progress::default(),
+        )
+        .await;
+        let state = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(summary.objects_skipped, recorded_keys.len());
+        assert_eq!(summary.objects_copied, 6 - recorded_keys.len());
+        for i in 0..6 {
+            let key = format!("clip{}.mp4", i);
+            let copied = new.object("videos", &key).is_some();
+            assert_eq!(copied, !recorded_keys.contains(&key), "{}", key);
+        }
+        assert!(state.starts_with(&recorded));
+        assert_eq!(state.lines().count(), 6);
+        assert!(state.ends_with('\n'));
+    }
+
+    fn listed(size: i64, e_tag: &str) -> Object {
+        Object::builder().size(size).e_tag(e_tag).build()
+    }
+
+    #[test]
+    fn same_content_needs_the_same_size_and_md5_etag() {
+        assert!(is_same_content(&listed(4, "\"a\""), &listed(4, "\"a\"")));
+        assert!(!is_same_content(&listed(4, "\"a\""), &listed(4, "\"b\"")));
+        assert!(!is_same_content(&listed(4, "\"a\""), &listed(5, "\"a\"")));
+        // Multipart ETags depend on the part size, so only the sizes count.
+        assert!(is_same_content(&listed(4, "\"a-2\""), &listed(4, "\"b\"")));
+        assert!(is_same_content(&listed(4, "\"a\""), &listed(4, "\"b-3\"")));
+        assert!(!is_same_content(
+            &listed(4, "\"a-2\""),
+            &listed(5, "\"a-2\"")
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_destination_objects_with_the_same_content_are_skipped() {
+        let old = FakeS3::default()
+            .with_object("photos", "same.jpg", b"meow")
+            .with_object("photos", "resized.jpg", b"woof!")
+            .with_object("photos", "edited.jpg", b"purr")
+            .with_object("photos", "missing.jpg", b"hiss");
+        let new = FakeS3::default()
+            .with_object("photos", "same.jpg", b"meow")
+            .with_object("photos", "resized.jpg", b"woof")
+            .with_object("photos", "edited.jpg", b"PURR");
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &test_options(),
+            &Progress::default(),
+        )
+        .await;
+
+        assert_eq!(summary.objects_skipped, 1);
+        assert_eq!(summary.objects_copied, 3);
+        for key in ["same.jpg", "resized.jpg", "edited.jpg", "missing.jpg"] {
+            assert_eq!(
+                new.object("photos", key),
+                old.object("photos", key),
+                "{}",
+                key
+            );
+        }
+        assert!(!new
+            .requests()
+            .iter()
+            .any(|request| request.starts_with("PUT /photos/same.jpg")));
+    }
+
+    #[tokio::test]
+    async fn case_collisions_abort_the_run_before_copying_the_bucket() {
+        let old = FakeS3::default()
+            .with_object("docs", "readme.txt", b"readme")
+            .with_object("photos", "Cat.jpg", b"meow")
+            .with_object("photos", "cat.jpg", b"purr")
+            .with_object("photos", "dog.jpg", b"woof");
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                buckets: Some(vec![
+                    BucketConfig::listed("photos".to_string()),
+                    BucketConfig::listed("docs".to_string()),
+                ]),
+                case_collisions: Some(CaseCollisions::Abort),
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.aborted);
+        assert_eq!(
+            summary.skipped_buckets,
+            vec![(
+                "photos".to_string(),
+                "case-insensitive key collisions: Cat.jpg, cat.jpg".to_string()
+            )]
+        );
+        assert_eq!(summary.objects_copied, 0);
+        assert_eq!(new.object("photos", "dog.jpg"), None);
+        assert_eq!(new.object("docs", "readme.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn strict_runs_stop_at_the_first_failed_object() {
+        let old = FakeS3::default()
+            .with_object("docs", "readme.txt", b"readme")
+            .with_object("photos", "cat.jpg", b"meow")
+            .with_object("photos", "secret.jpg", b"hidden")
+            .with_object("photos", "zebra.jpg", b"stripes")
+            .deny("photos", "secret.jpg");
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                buckets: Some(vec![
+                    BucketConfig::listed("photos".to_string()),
+                    BucketConfig::listed("docs".to_string()),
+                ]),
+                strict: true,
+                object_concurrency: 1,
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.aborted);
+        assert!(summary.strict_failure);
+        assert!(!summary.failure_limit_reached);
+        assert_eq!(summary.objects_copied, 1);
+        assert_eq!(summary.failed_objects.len(), 1);
+        assert_eq!(summary.failed_objects[0].0, "photos/secret.jpg");
+        assert!(summary.failed_objects[0].1.contains("AccessDenied"));
+        assert!(summary.to_json().contains("\"strict_failure\":true"));
+        assert_eq!(new.object("photos", "cat.jpg"), Some(b"meow".to_vec()));
+        assert_eq!(new.object("photos", "zebra.jpg"), None);
+        assert_eq!(new.object("docs", "readme.txt"), None);
+    }
+
+    #[tokio::test]
+    async fn only_objects_under_the_prefixes_are_copied() {
+        let old = FakeS3::default()
+            .with_object("photos", "2023/cat.jpg", b"old cat")
+            .with_object("photos", "2024/cat.jpg", b"meow")
+            .with_object("photos", "2024/dog.jpg", b"woof!")
+            .with_object("photos", "docs/readme.txt", b"readme")
+            .with_object("photos", "root.jpg", b"root");
+        let new = FakeS3::default()
+            .with_object("photos", "2024/dog.jpg", b"woof!")
+            .with_object("photos", "2023/cat.jpg", b"old cat");
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                prefixes: Some(vec!["2024/".to_string(), "docs/".to_string()]),
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert_eq!(summary.objects_copied, 2);
+        assert_eq!(summary.objects_skipped, 1);
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(
+            new.object("photos", "2024/cat.jpg").as_deref(),
+            Some(&b"meow"[..])
+        );
+        assert_eq!(
+            new.object("photos", "docs/readme.txt").as_deref(),
+            Some(&b"readme"[..])
+        );
+        assert_eq!(new.object("photos", "root.jpg"), None);
+        let listings = new
+            .requests()
+            .into_iter()
+            .filter(|request| request.starts_with("GET /photos/?list-type"))
+            .collect::<Vec<String>>();
+        assert_eq!(listings.len(), 2);
+        assert!(listings
+            .iter()
+            .all(|request| request.contains("prefix=2024") || request.contains("prefix=docs")));
+    }
+
+    #[test]
+    fn globs_match_any_run_of_characters() {
+        assert!(matches_glob("prod-*", "prod-logs"));
+        assert!(matches_glob("prod-*", "prod-"));
+        assert!(!matches_glob("prod-*", "staging-prod-logs"));
+        assert!(matches_glob("*-logs", "prod-logs"));
+        assert!(matches_glob("prod-*-2024", "prod-logs-2024"));
+        assert!(!matches_glob("prod-*-2024", "prod-logs-2023"));
+        assert!(matches_glob("a*b*c", "abc"));
+        assert!(!matches_glob("ab*b", "ab"));
+        assert!(matches_glob("*", ""));
+        assert!(matches_glob("logs", "logs"));
+        assert!(!matches_glob("logs", "logs2"));
+    }
+
+    #[tokio::test]
+    async fn buckets_are_filtered_by_include_then_exclude() {
+        let copied_buckets = |include: Option<&[&str]>, exclude: &[&str]| {
+            let to_strings = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+            let options = RunOptions {
+                include_buckets: include.map(to_strings),
+                exclude_buckets: to_strings(exclude),
+                ..test_options()
+            };
+            async move {
+                let old = ["prod-logs", "prod-photos", "staging-logs", "docs"]
+                    .into_iter()
+                    .fold(FakeS3::default(), |old, bucket| {
+                        old.with_object(bucket, "a.txt", b"a")
+                    });
+                let new = FakeS3::default();
+                run(&old.client(), &new.client(), &options, &Progress::default()).await;
+                ["prod-logs", "prod-photos", "staging-logs", "docs"]
+                    .into_iter()
+                    .filter(|bucket| new.object(bucket, "a.txt").is_some())
+                    .collect::<Vec<&str>>()
+            }
+        };
+
+        assert_eq!(
+            copied_buckets(Some(&["prod-*", "docs"]), &[]).await,
+            ["prod-logs", "prod-photos", "docs"]
+        );
+        assert_eq!(
+            copied_buckets(None, &["*-logs"]).await,
+            ["prod-photos", "docs"]
+        );
+        assert_eq!(
+            copied_buckets(Some(&["prod-*"]), &["prod-photos"]).await,
+            ["prod-logs"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn objects_are_copied_concurrently_up_to_the_object_concurrency() {
+        let keys = (0..6)
+            .map(|i| format!("clip-{}.mp4", i))
+            .collect::<Vec<String>>();
+        let old = keys.iter().fold(FakeS3::default(), |old, key| {
+            old.with_object("videos", key, &[7; 16])
+        });
+        let new = FakeS3::default().with_part_delay(Duration::from_millis(20));
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                object_concurrency: 3,
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    max_concurrent_parts: 1,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(summary.objects_copied, 6);
+        for key in &keys {
+            assert_eq!(new.object("videos", key), Some(vec![7; 16]), "{}", key);
+        }
+        // One part at a time per object, so the parts in flight are those of different objects.
+        let peak = new.peak_parts_in_flight();
+        assert!((2..=3).contains(&peak), "{} parts in flight", peak);
+    }
+
+    #[tokio::test]
+    async fn run_summary_counts_copied_skipped_and_failed_objects() {
+        let old = FakeS3::default()
+            .with_object("photos", "2024/cat.jpg", b"meow")
+            .with_object("photos", "2024/dog.jpg", b"woof!")
+            .with_object("photos", "secret.jpg", b"hidden")
+            .deny("photos", "secret.jpg");
+        let new = FakeS3::default().with_object("photos", "2024/dog.jpg", b"woof!");
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &test_options(),
+            &Progress::default(),
+        )
+        .await;
+
+        assert_eq!(summary.buckets, 1);
+        assert_eq!(summary.objects_copied, 1);
+        assert_eq!(summary.objects_skipped, 1);
+        assert_eq!(summary.singlepart_objects, 1);
+        assert_eq!(summary.multipart_objects, 0);
+        assert_eq!(summary.bytes, 4);
+        assert_eq!(summary.failed_objects.len(), 1);
+        assert_eq!(summary.failed_objects[0].0, "photos/secret.jpg");
+        assert!(summary.failed_objects[0].1.contains("AccessDenied"));
+        assert!(summary.skipped_buckets.is_empty());
+        assert!(!summary.aborted);
+        assert_eq!(
+            new.object("photos", "2024/cat.jpg").as_deref(),
+            Some(&b"meow"[..])
+        );
+        assert_eq!(new.object("photos", "secret.jpg"), None);
+    }
+
+    #[tokio::test]
+    async fn copies_keep_the_user_metadata_of_their_source() {
+        let old = FakeS3::default()
+            .with_stored_object(
+                "docs",
+                "a/report.pdf",
+                stored_object(b"data", &[("x-amz-meta-author", "alice")]),
+            )
+            .with_stored_object(
+                "docs",
+                "b/report.pdf",
+                stored_object(
+                    b"data",
+                    &[("x-amz-meta-author", "bob"), ("x-amz-meta-draft", "yes")],
+                ),
+            )
+            .with_stored_object(
+                "docs",
+                "c/archive.zip",
+                stored_object(b"large archive", &[("x-amz-meta-author", "carol")]),
+            )
+            .with_object("docs", "d/plain.txt", b"plain");
+        let new = FakeS3::default();
+
+        copy_with_every_path(
+            &old,
+            &new,
+            RunOptions {
+                preserve_ownership_metadata: true,
+                ..test_options()
+            },
+        )
+        .await;
+
+        for (key, metadata) in [
+            ("a/report.pdf", vec![("author", "alice")]),
+            ("b/report.pdf", vec![("author", "bob"), ("draft", "yes")]),
+            ("c/archive.zip", vec![("author", "carol")]),
+            ("d/plain.txt", vec![]),
+        ] {
+            let mut expected = metadata
+                .into_iter()
+                .map(|(name, value)| (format!("x-amz-meta-{}", name), value.to_string()))
+                .collect::<BTreeMap<String, String>>();
+            expected.insert("x-amz-meta-original-owner".to_string(), OWNER.to_string());
+            let mut user_metadata = new.stored_object("docs", key).unwrap().headers;
+            user_metadata.retain(|name, _| name.starts_with("x-amz-meta-"));
+            assert_eq!(user_metadata, expected, "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn strict_runs_fail_objects_whose_expires_cannot_be_preserved() {
+        let old = FakeS3::default()
+            .with_stored_object(
+                "assets",
+                "app.js",
+                stored_object(b"gzip", &[("expires", "0")]),
+            )
+            .with_object("assets", "index.html", b"<html>");
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                strict: true,
+                object_concurrency: 1,
+                upload_options: UploadOptions {
+                    strict: true,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.strict_failure);
+        assert_eq!(summary.failed_objects.len(), 1);
+        assert_eq!(summary.failed_objects[0].0, "assets/app.js");
+        assert!(summary.failed_objects[0].1.contains("not an HTTP date"));
+        assert_eq!(new.object("assets", "app.js"), None);
+    }
+
+    #[tokio::test]
+    async fn strict_runs_fail_objects_whose_storage_class_has_no_mapping() {
+        let path = env::temp_dir().join(format!("s3copy-storage-classes-{}", process::id()));
+        fs::write(&path, "GLACIER\tARCHIVE\n").unwrap();
+        let old = FakeS3::default().with_object("photos", "cat.jpg", b"meow");
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                strict: true,
+                upload_options: UploadOptions {
+                    preserve_storage_class: true,
+                    storage_class_map: Some(StorageClassMap::load(path.to_str().unwrap())),
+                    strict: true,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+        fs::remove_file(&path).unwrap();
+
+        assert!(summary.strict_failure);
+        assert_eq!(summary.unmapped_storage_classes["STANDARD"], 1);
+        assert_eq!(summary.failed_objects.len(), 1);
+        assert!(summary.failed_objects[0].1.contains("STORAGE_CLASS_MAP"));
+        assert_eq!(new.object("photos", "cat.jpg"), None);
+    }
+
+    #[tokio::test]
+    async fn copies_keep_the_content_headers_of_their_source() {
+        let headers = [
+            ("content-encoding", "gzip"),
+            ("cache-control", "public, max-age=31536000"),
+            ("content-disposition", "attachment; filename=\"app.js\""),
+            ("expires", "Wed, 01 Jan 2025 00:00:00 GMT"),
+        ];
+        let old = FakeS3::default()
+            .with_stored_object("assets", "a/app.js", stored_object(b"gzip", &headers))
+            .with_stored_object("assets", "b/app.js", stored_object(b"gzip", &headers))
+            .with_stored_object(
+                "assets",
+                "c/vendor.js",
+                stored_object(b"large gzip", &headers),
+            );
+        let new = FakeS3::default();
+
+        copy_with_every_path(&old, &new, test_options()).await;
+
+        for key in ["a/app.js", "b/app.js", "c/vendor.js"] {
+            let object = new.stored_object("assets", key).unwrap();
+            for (name, value) in headers {
+                assert_eq!(object.headers[name], value, "{} of {}", name, key);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn abort_on_failures_counts_consecutive_or_total_failures() {
+        let old = FakeS3::default()
+            .with_object("logs", "a.log", b"a")
+            .with_object("logs", "b.log", b"b")
+            .with_object("logs", "c.log", b"c")
+            .with_object("logs", "d.log", b"d")
+            .with_object("logs", "e.log", b"e")
+            .deny("logs", "a.log")
+            .deny("logs", "c.log")
+            .deny("logs", "d.log");
+
+        for (failure_count, limit, copied) in [
+            (FailureCount::Consecutive, 2, vec!["b.log"]),
+            (FailureCount::Consecutive, 3, vec!["b.log", "e.log"]),
+            (FailureCount::Total, 3, vec!["b.log"]),
+        ] {
+            let new = FakeS3::default();
+            let summary = run(
+                &old.client(),
+                &new.client(),
+                &RunOptions {
+                    abort_on_failures: Some(limit),
+                    failure_count,
+                    object_concurrency: 1,
+                    ..test_options()
+                },
+                &Progress::default(),
+            )
+            .await;
+
+            let limit_reached = copied.len() == 1;
+            assert_eq!(summary.failure_limit_reached, limit_reached);
+            assert_eq!(summary.aborted, limit_reached);
+            assert_eq!(summary.objects_copied, copied.len());
+            for key in copied {
+                assert!(new.object("logs", key).is_some());
+            }
+            assert_eq!(new.object("logs", "e.log").is_some(), !limit_reached);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn transfer_limit_is_shared_by_every_bucket_object_and_part() {
+        let old = FakeS3::default()
+            .with_object("logs", "a.log", b"first log line")
+            .with_object("logs", "b.log", b"second log line")
+            .with_object("photos", "cat.jpg", b"meow")
+            .with_object("photos", "dog.jpg", b"a long bark");
+        let new = FakeS3::default().with_part_delay(Duration::from_millis(20));
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                buckets: Some(vec![
+                    BucketConfig::listed("logs".to_string()),
+                    BucketConfig {
+                        object_concurrency: Some(1),
+                        ..BucketConfig::listed("photos".to_string())
+                    },
+                ]),
+                object_concurrency: 4,
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    max_concurrent_parts: 4,
+                    transfer_limit: Some(RequestLimit::new(2)),
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(summary.objects_copied, 4);
+        assert_eq!(summary.multipart_objects, 3);
+        for (bucket, key) in [
+            ("logs", "a.log"),
+            ("logs", "b.log"),
+            ("photos", "cat.jpg"),
+            ("photos", "dog.jpg"),
+        ] {
+            assert_eq!(new.object(bucket, key), old.object(bucket, key), "{}", key);
+        }
+        // Up to 8 parts of the logs would be in flight without the limit.
+        assert_eq!(new.peak_parts_in_flight(), 2);
+    }
+
+    #[tokio::test]
+    async fn head_limit_is_shared_by_every_metadata_request() {
+        let tagged = |content: &[u8]| StoredObject {
+            tagging: Some("team=web".to_string()),
+            ..stored_object(content, &[])
+        };
+        let old = FakeS3::default()
+            .with_stored_object("site", "a/logo.png", tagged(b"logo"))
+            .with_stored_object("site", "b/logo.png", tagged(b"logo"))
+            .with_stored_object("site", "c/photo.jpg", tagged(b"large photo"));
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                dedup: true,
+                object_concurrency: 4,
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    verify_size: true,
+                    head_limit: Some(RequestLimit::new(1)),
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.failed_objects.is_empty());
+        assert_eq!(summary.objects_copied, 3);
+        for key in ["a/logo.png", "b/logo.png", "c/photo.jpg"] {
+            let object = new.stored_object("site", key).unwrap();
+            assert_eq!(object.content, old.object("site", key).unwrap());
+            assert_eq!(object.tagging.as_deref(), Some("team=web"), "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn objects_of_the_wrong_size_at_the_destination_fail_verification() {
+        let old = FakeS3::default()
+            .with_object("photos", "cat.jpg", b"meow")
+            .with_object("photos", "dog.jpg", b"woof");
+        let new = FakeS3::default().misreport_size("photos", "cat.jpg");
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                upload_options: UploadOptions {
+                    verify_size: true,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert_eq!(summary.objects_copied, 1);
+        assert_eq!(summary.failed_objects.len(), 1);
+        assert_eq!(summary.failed_objects[0].0, "photos/cat.jpg");
+        assert!(
+            summary.failed_objects[0]
+                .1
+                .contains("Size mismatch for cat.jpg: source 4 bytes, destination 3 bytes"),
+            "{}",
+            summary.failed_objects[0].1
+        );
+    }
+
+    #[tokio::test]
+    async fn object_tags_are_copied_with_the_object() {
+        let tagged = |content: &[u8]| StoredObject {
+            tagging: Some("env=prod&team=data".to_string()),
+            ..stored_object(content, &[])
+        };
+        let old = FakeS3::default()
+            .with_stored_object("photos", "cat.jpg", tagged(b"meow"))
+            .with_stored_object("photos", "clip.mp4", tagged(b"a long video clip"))
+            .with_object("photos", "dog.jpg", b"woof");
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                upload_options: UploadOptions {
+                    multipart_threshold: 8,
+                    part_size: 4,
+                    ..Default::default()
+                },
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert_eq!(summary.objects_copied, 3);
+        let tags = |s3: &FakeS3, key: &'static str| {
+            let client = s3.client();
+            async move {
+                client
+                    .get_object_tagging()
+                    .bucket("photos")
+                    .key(key)
+                    .send()
+                    .await
+                    .unwrap()
+                    .tag_set
+                    .into_iter()
+                    .map(|tag| (tag.key, tag.value))
+                    .collect::<Vec<(String, String)>>()
+            }
+        };
+        for key in ["cat.jpg", "clip.mp4"] {
+            assert_eq!(tags(&new, key).await, tags(&old, key).await, "{}", key);
+            assert_eq!(
+                new.stored_object("photos", key).unwrap().tagging.as_deref(),
+                Some("env=prod&team=data")
+            );
+        }
+        assert!(tags(&new, "dog.jpg").await.is_empty());
+        // The untagged object is copied without reading its tags.
+        let requests = old.requests();
+        assert!(requests.contains(&"GET /photos/cat.jpg?tagging".to_string()));
+        assert!(!requests.contains(&"GET /photos/dog.jpg?tagging".to_string()));
+    }
+
+    #[tokio::test]
+    async fn folder_markers_are_only_copied_when_the_source_has_them() {
+        let old = FakeS3::default()
+            .with_object("photos", "2024/05/cat.jpg", b"meow")
+            .with_object("photos", "2024/dog.jpg", b"woof")
+            .with_object("docs", "reports/", b"")
+            .with_object("docs", "reports/q1.pdf", b"q1");
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                buckets: Some(vec![
+                    BucketConfig::listed("photos".to_string()),
+                    BucketConfig::listed("docs".to_string()),
+                ]),
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert_eq!(summary.objects_copied, 4);
+        let keys = |bucket: &'static str| {
+            let client = new.client();
+            async move {
+                client
+                    .list_objects_v2()
+                    .bucket(bucket)
+                    .send()
+                    .await
+                    .unwrap()
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|object| object.key.unwrap())
+                    .collect::<Vec<String>>()
+            }
+        };
+        assert_eq!(keys("photos").await, ["2024/05/cat.jpg", "2024/dog.jpg"]);
+        assert_eq!(keys("docs").await, ["reports/", "reports/q1.pdf"]);
+    }
+
+    #[tokio::test]
+    async fn retention_only_runs_create_no_buckets_and_stop_like_copies() {
+        let old = FakeS3::default()
+            .with_object("docs", "readme.txt", b"readme")
+            .with_object("photos", "cat.jpg", b"meow")
+            .with_object("photos", "dog.jpg", b"woof!")
+            .with_object("photos", "fox.jpg", b"yip")
+            .deny("photos", "dog.jpg")
+            .deny("photos", "fox.jpg");
+        let new = FakeS3::default()
+            .with_object("photos", "cat.jpg", b"meow")
+            .with_object("photos", "dog.jpg", b"woof!")
+            .with_object("photos", "fox.jpg", b"yip");
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                buckets: Some(vec![
+                    BucketConfig::listed("docs".to_string()),
+                    BucketConfig::listed("photos".to_string()),
+                ]),
+                retention_only: true,
+                strict: true,
+                object_concurrency: 1,
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(new
+            .requests()
+            .iter()
+            .all(|request| !request.starts_with("PUT /docs")));
+        assert_eq!(summary.skipped_buckets.len(), 1);
+        assert_eq!(summary.skipped_buckets[0].0, "docs");
+        assert!(summary.strict_failure);
+        assert_eq!(summary.failed_objects.len(), 1);
+        assert_eq!(summary.failed_objects[0].0, "photos/dog.jpg");
+        assert_eq!(summary.retention_updated, Some(0));
+    }
+
+    #[tokio::test]
+    async fn dry_run_counts_objects_without_writing() {
+        let old = FakeS3::default()
+            .with_object("photos", "cat.jpg", b"meow")
+            .with_object("photos", "dog.jpg", b"woof!")
+            .with_object("photos", ".env", b"SECRET=1");
+        let new = FakeS3::default().with_object("photos", "dog.jpg", b"woof!");
+        let plan_output = env::temp_dir().join(format!("s3copy-plan-{}.txt", process::id()));
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                dry_run: true,
+                plan_output: Some(plan_output.to_str().unwrap().to_string()),
+                deny_keys: HashSet::from([".env".to_string()]),
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.dry_run);
+        assert_eq!(summary.objects_copied, 1);
+        assert_eq!(summary.objects_skipped, 1);
+        assert_eq!(summary.bytes, 4);
+        assert!(new
+            .requests()
+            .iter()
+            .all(|request| !request.starts_with("PUT")));
+        let plan = fs::read_to_string(&plan_output).unwrap();
+        fs::remove_file(&plan_output).unwrap();
+        assert!(plan.contains("copy      photos/cat.jpg -> photos/cat.jpg (4 bytes)"));
+        assert!(plan.contains("skip      photos/dog.jpg -> photos/dog.jpg (5 bytes)"));
+        assert!(plan.contains("skip      photos/.env -> photos/.env (8 bytes, denied)"));
+    }
+
+    #[cfg(feature = "presigned")]
+    #[tokio::test]
+    async fn presigned_dry_run_uploads_nothing() {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        // Serves every request with the same object, like a presigned GET URL would.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\ncontent-type: image/jpeg\r\n\r\nmeow")
+                    .await;
+            }
+        });
+        let urls = env::temp_dir().join(format!("s3copy-presigned-{}.txt", process::id()));
+        fs::write(
+            &urls,
+            format!(
+                "http://{}/cat.jpg?X-Amz-Signature=secret\tcat.jpg\nhttp://{}/dog.jpg\tdog.jpg\n",
+                address, address
+            ),
+        )
+        .unwrap();
+        let new = FakeS3::default().with_object("uploads", "existing.jpg", b"old");
+
+        let summary = run(
+            &FakeS3::default().client(),
+            &new.client(),
+            &RunOptions {
+                dry_run: true,
+                presigned_urls: Some(urls.to_str().unwrap().to_string()),
+                presigned_bucket: Some("uploads".to_string()),
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+        fs::remove_file(&urls).unwrap();
+
+        assert!(summary.dry_run);
+        assert_eq!(summary.objects_copied, 2);
+        assert_eq!(summary.bytes, 8);
+        assert!(summary.failed_objects.is_empty());
+        assert!(new.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn max_object_count_aborts_before_the_destination_is_touched() {
+        let old = FakeS3::default()
+            .with_object("photos", "a.jpg", b"a")
+            .with_object("photos", "b.jpg", b"b")
+            .with_object("photos", "c.jpg", b"c");
+        let new = FakeS3::default();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                max_object_count: Some(2),
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.aborted);
+        assert_eq!(summary.skipped_buckets.len(), 1);
+        assert_eq!(summary.skipped_buckets[0].0, "photos");
+        assert_eq!(summary.objects_copied, 0);
+        assert!(new
+            .requests()
+            .iter()
+            .all(|request| !request.starts_with("PUT")));
+    }
+
+    #[tokio::test]
+    async fn unlistable_buckets_are_skipped_or_end_the_run_with_fail_fast() {
+        let old = FakeS3::default().with_object("photos", "cat.jpg", b"meow");
+        let buckets = || {
+            Some(vec![
+                BucketConfig::listed("missing".to_string()),
+                BucketConfig::listed("photos".to_string()),
+            ])
+        };
+
+        let new = FakeS3::default();
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                buckets: buckets(),
+                list_page_attempts: 1,
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(!summary.aborted);
+        assert_eq!(summary.skipped_buckets.len(), 1);
+        assert_eq!(summary.skipped_buckets[0].0, "missing");
+        assert!(summary.skipped_buckets[0]
+            .1
+            .starts_with("listing failed: missing/*: "));
+        assert_eq!(new.object("photos", "cat.jpg"), Some(b"meow".to_vec()));
+
+        let new = FakeS3::default();
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                buckets: buckets(),
+                list_page_attempts: 1,
+                fail_fast_on_bucket_error: true,
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.aborted);
+        assert_eq!(summary.skipped_buckets.len(), 1);
+        assert_eq!(summary.skipped_buckets[0].0, "missing");
+        assert_eq!(summary.objects_copied, 0);
+        assert!(new.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn require_empty_destination_checks_every_bucket_before_copying() {
+        let old = FakeS3::default()
+            .with_object("docs", "a.txt", b"a")
+            .with_object("photos", "cat.jpg", b"meow");
+        let new = FakeS3::default().with_object("photos", "stale.jpg", b"old");
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                require_empty_destination: true,
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.aborted);
+        assert_eq!(summary.skipped_buckets.len(), 1);
+        assert_eq!(summary.skipped_buckets[0].0, "photos");
+        assert!(summary.skipped_buckets[0]
+            .1
+            .contains("not empty (1 objects)"));
+        assert_eq!(summary.objects_copied, 0);
+        assert_eq!(new.object("docs", "a.txt"), None);
+        assert!(new
+            .requests()
+            .iter()
+            .all(|request| !request.starts_with("PUT")));
+    }
+
+    #[tokio::test]
+    async fn cancelled_run_still_returns_the_summary() {
+        let old = FakeS3::default().with_object("photos", "cat.jpg", b"meow");
+        let new = FakeS3::default();
+        let cancel = Arc::new(Notify::new());
+        cancel.notify_one();
+
+        let summary = run(
+            &old.client(),
+            &new.client(),
+            &RunOptions {
+                cancel: Some(cancel),
+                ..test_options()
+            },
+            &Progress::default(),
+        )
+        .await;
+
+        assert!(summary.aborted);
+        assert_eq!(summary.objects_copied, 0);
+        assert_eq!(new.object("photos", "cat.jpg"), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn cancelled_runs_abort_the_multipart_uploads_in_flight() {
+        let old = FakeS3::default().with_object("videos", "clip.mp4", b"a long video clip");
+        let new = FakeS3::default().with_part_delay(Duration::from_millis(200));
+        let cancel = Arc::new(Notify::new());
+        let options = RunOptions {
+            upload_options: UploadOptions {
+                multipart_threshold: 8,
+                part_size: 4,
+                max_concurrent_parts: 1,
+                ..Default::default()
+            },
+            cancel: Some(cancel.clone()),
+            ..test_options()
+        };
+        let (old_client, new_client) = (old.client(), new.client());
+        let progress = Progress::default();
+
+        let (summary, _) =
+            tokio::join!(run(&old_client, &new_client, &options, &progress), async {
+                while !new
+                    .requests()
+                    .iter()
+                    .any(|request| request.contains("x-id=UploadPart&"))
+                {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                cancel.notify_one();
+            });
+
+        assert!(summary.aborted);
+        assert_eq!(summary.objects_copied, 0);
+        assert_eq!(new.object("videos", "clip.mp4"), None);
+        assert_eq!(new.pending_uploads(), 0);
+        assert!(new.requests().iter().any(|request| {
+            request.starts_with("DELETE /videos/clip.mp4?") && request.contains("uploadId=upload-1")
+        }));
+        // The part tasks were aborted with the upload, so the rest of the 5 parts are never sent.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let part_uploads = new
+            .requests()
+            .iter()
+            .filter(|request| request.contains("x-id=UploadPart&"))
+            .count();
+        assert!(part_uploads < 5, "{} parts were sent", part_uploads);
+    }
+
+    #[tokio::test]
+    async fn idle_watchdog_only_fires_while_objects_are_copied() {
+        let idle_watchdog = IdleWatchdog::new(Duration::from_millis(10));
+        let wait = IDLE_CHECK_INTERVAL + Duration::from_millis(500);
+        assert!(tokio::time::timeout(wait, idle_watchdog.idle())
+            .await
+            .is_err());
+
+        idle_watchdog.progress();
+        assert!(tokio::time::timeout(wait, idle_watchdog.idle())
+            .await
+            .is_ok());
+
+        idle_watchdog.stop();
+        assert!(tokio::time::timeout(wait, idle_watchdog.idle())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn summary_json_escapes_keys_and_errors() {
+        let summary = RunSummary {
+            buckets: 1,
+            objects_copied: 2,
+            failed_objects: vec![(
+                "photos/\"quoted\"\n.jpg".to_string(),
+                "ServiceError { raw: \"\\u{1}\" }".to_string(),
+            )],
+            unlisted_ranges: vec!["photos/日本*".to_string()],
+            duration: Duration::from_millis(1500),
+            ..Default::default()
+        };
+        let json: serde_json::Value = serde_json::from_str(&summary.to_json()).unwrap();
+        assert_eq!(json["buckets"], 1);
+        assert_eq!(json["objects_copied"], 2);
+        assert_eq!(json["objects_failed"], 1);
+        assert_eq!(
+            json["failed_objects"][0]["object"],
+            "photos/\"quoted\"\n.jpg"
+        );
+        assert_eq!(
+            json["failed_objects"][0]["error"],
+            "ServiceError { raw: \"\\u{1}\" }"
+        );
+        assert_eq!(json["unlisted_ranges"][0], "photos/日本*");
+        assert_eq!(json["duration_secs"], 1.5);
+    }
+}
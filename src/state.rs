@@ -0,0 +1,101 @@
+use std::{collections::HashMap, fs};
+
+use aws_sdk_s3::types::Object;
+use serde::Deserialize;
+
+use crate::manifest::Manifest;
+
+#[derive(Deserialize)]
+struct Entry {
+    bucket: String,
+    key: String,
+    size: i64,
+    etag: Option<String>,
+}
+
+/// Objects finished by earlier runs, kept in a file in the manifest's format that this run
+/// appends to as each object completes.
+pub struct State {
+    /// Size and ETag of the source of each finished destination bucket and key.
+    finished: HashMap<(String, String), (i64, Option<String>)>,
+    manifest: Manifest,
+}
+
+impl State {
+    pub fn open(path: String) -> Self {
+        // Opening first drops a last line cut off by a crash.
+        let manifest = Manifest::open(path.clone());
+        let finished = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let entry: Entry = serde_json::from_str(line)
+                    .unwrap_or_else(|e| panic!("Invalid line in {}: {}: {}", path, line, e));
+                ((entry.bucket, entry.key), (entry.size, entry.etag))
+            })
+            .collect();
+        State { finished, manifest }
+    }
+
+    /// Whether an earlier run finished copying the object to `bucket`/`key` and the source has
+    /// not changed since.
+    pub fn is_finished(&self, bucket: &str, key: &str, object: &Object) -> bool {
+        self.finished
+            .get(&(bucket.to_string(), key.to_string()))
+            .is_some_and(|(size, etag)| {
+                object.size == Some(*size)
+                    && object.e_tag.as_deref().map(|e_tag| e_tag.trim_matches('"'))
+                        == etag.as_deref()
+            })
+    }
+
+    pub fn record(&mut self, bucket: &str, key: &str, object: &Object) {
+        self.manifest.record(bucket, key, object, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn listed(size: i64, e_tag: &str) -> Object {
+        Object::builder().size(size).e_tag(e_tag).build()
+    }
+
+    #[test]
+    fn only_recorded_objects_with_an_unchanged_source_are_finished() {
+        let path = env::temp_dir().join(format!("s3copy-state-{}", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+
+        let mut state = State::open(path.clone());
+        state.record("photos", "cat.jpg", &listed(4, "\"a\""));
+        state.record("photos", "dog.jpg", &listed(11, "\"b-2\""));
+
+        let state = State::open(path.clone());
+        fs::remove_file(&path).unwrap();
+        assert!(state.is_finished("photos", "cat.jpg", &listed(4, "\"a\"")));
+        assert!(state.is_finished("photos", "dog.jpg", &listed(11, "\"b-2\"")));
+        assert!(!state.is_finished("photos", "cat.jpg", &listed(5, "\"a\"")));
+        assert!(!state.is_finished("photos", "cat.jpg", &listed(4, "\"c\"")));
+        assert!(!state.is_finished("archive", "cat.jpg", &listed(4, "\"a\"")));
+        assert!(!state.is_finished("photos", "bird.jpg", &listed(4, "\"a\"")));
+    }
+
+    #[test]
+    fn a_last_line_cut_off_by_a_crash_is_dropped() {
+        let path = env::temp_dir().join(format!("s3copy-cut-state-{}", process::id()));
+        let complete = "{\"bucket\":\"photos\",\"key\":\"cat.jpg\",\"size\":4,\"etag\":\"a\"}\n";
+        fs::write(&path, format!("{}{{\"bucket\":\"photos\",\"ke", complete)).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let state = State::open(path.clone());
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents, complete);
+        assert!(state.is_finished("photos", "cat.jpg", &listed(4, "\"a\"")));
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Gauge, List, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+use s3copy::progress::ProgressEvent;
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, Notify},
+    time::Instant,
+};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+/// Throughput is averaged over the objects finished this long ago at most.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(10);
+const RECENT_ERRORS: usize = 10;
+
+struct BucketProgress {
+    name: String,
+    objects: usize,
+    bytes: i64,
+    done_objects: usize,
+    done_bytes: i64,
+    failed_objects: usize,
+}
+
+struct Dashboard {
+    started: Instant,
+    buckets: Vec<BucketProgress>,
+    /// Keys in flight with their sizes. Buckets are copied one at a time, so keys are unique.
+    active: BTreeMap<String, i64>,
+    errors: VecDeque<String>,
+    finished: VecDeque<(Instant, i64)>,
+    transferred: i64,
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+impl Dashboard {
+    fn apply(&mut self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::Bucket {
+                name,
+                objects,
+                bytes,
+            } => self.buckets.push(BucketProgress {
+                name,
+                objects,
+                bytes,
+                done_objects: 0,
+                done_bytes: 0,
+                failed_objects: 0,
+            }),
+            ProgressEvent::Started { key, size } => {
+                self.active.insert(key, size);
+            }
+            ProgressEvent::Finished { key, bytes } => {
+                let size = self.active.remove(&key).unwrap_or(bytes);
+                self.finished.push_back((Instant::now(), bytes));
+                self.transferred += bytes;
+                if let Some(bucket) = self.buckets.last_mut() {
+                    bucket.done_objects += 1;
+                    bucket.done_bytes += size;
+                }
+            }
+            ProgressEvent::Failed { key, error } => {
+                let size = self.active.remove(&key).unwrap_or_default();
+                if let Some(bucket) = self.buckets.last_mut() {
+                    bucket.done_objects += 1;
+                    bucket.done_bytes += size;
+                    bucket.failed_objects += 1;
+                }
+                if self.errors.len() == RECENT_ERRORS {
+                    self.errors.pop_front();
+                }
+                self.errors.push_back(format!("{}: {}", key, error));
+            }
+        }
+    }
+
+    fn throughput(&mut self) -> f64 {
+        while self
+            .finished
+            .front()
+            .is_some_and(|(at, _)| at.elapsed() > THROUGHPUT_WINDOW)
+        {
+            self.finished.pop_front();
+        }
+        let window = self.started.elapsed().min(THROUGHPUT_WINDOW);
+        let bytes: i64 = self.finished.iter().map(|(_, bytes)| bytes).sum();
+        bytes as f64 / window.as_secs_f64().max(1.0)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [status, current, buckets, active, errors] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Min(4),
+            Constraint::Min(4),
+            Constraint::Length(RECENT_ERRORS as u16 + 2),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(format!(
+                "Elapsed {}s | Transferred {} | Throughput {}/s | q to quit",
+                self.started.elapsed().as_secs(),
+                format_bytes(self.transferred as f64),
+                format_bytes(self.throughput()),
+            )),
+            status,
+        );
+
+        if let Some(bucket) = self.buckets.last() {
+            let ratio = if bucket.bytes > 0 {
+                bucket.done_bytes as f64 / bucket.bytes as f64
+            } else if bucket.objects > 0 {
+                bucket.done_objects as f64 / bucket.objects as f64
+            } else {
+                1.0
+            };
+            frame.render_widget(
+                Gauge::default()
+                    .block(Block::bordered().title(bucket.name.as_str()))
+                    .gauge_style(Style::default().fg(Color::Green))
+                    .ratio(ratio.clamp(0.0, 1.0))
+                    .label(format!(
+                        "{}/{} objects, {} of {}",
+                        bucket.done_objects,
+                        bucket.objects,
+                        format_bytes(bucket.done_bytes as f64),
+                        format_bytes(bucket.bytes as f64)
+                    )),
+                current,
+            );
+        }
+
+        frame.render_widget(
+            Table::new(
+                self.buckets.iter().rev().map(|bucket| {
+                    Row::new([
+                        bucket.name.clone(),
+                        format!("{}/{}", bucket.done_objects, bucket.objects),
+                        format!(
+                            "{} / {}",
+                            format_bytes(bucket.done_bytes as f64),
+                            format_bytes(bucket.bytes as f64)
+                        ),
+                        bucket.failed_objects.to_string(),
+                    ])
+                }),
+                [
+                    Constraint::Fill(2),
+                    Constraint::Fill(1),
+                    Constraint::Fill(1),
+                    Constraint::Length(6),
+                ],
+            )
+            .header(Row::new(["Bucket", "Objects", "Bytes", "Failed"]))
+            .block(Block::bordered().title("Buckets")),
+            buckets,
+        );
+
+        frame.render_widget(
+            List::new(
+                self.active
+                    .iter()
+                    .map(|(key, size)| format!("{} ({})", key, format_bytes(*size as f64))),
+            )
+            .block(Block::bordered().title(format!("Active ({})", self.active.len()))),
+            active,
+        );
+
+        frame.render_widget(
+            List::new(
+                self.errors
+                    .iter()
+                    .rev()
+                    .map(|error| Line::styled(error.as_str(), Style::default().fg(Color::Red))),
+            )
+            .block(Block::bordered().title("Recent errors")),
+            errors,
+        );
+    }
+}
+
+/// Whether the user asked to quit with `q` or Ctrl-C, which raw mode no longer turns into a
+/// signal.
+fn quit_requested() -> bool {
+    while event::poll(Duration::ZERO).unwrap_or(false) {
+        if let Ok(Event::Key(key)) = event::read() {
+            if key.kind == KeyEventKind::Press
+                && (key.code == KeyCode::Char('q')
+                    || (key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL)))
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn redraw(terminal: &mut DefaultTerminal, dashboard: &mut Dashboard) {
+    // A failed draw only loses a frame; the copy goes on.
+    let _ = terminal.draw(|frame| dashboard.draw(frame));
+}
+
+/// Shows the dashboard until every sender of `events` is dropped, then restores the terminal.
+/// Quitting notifies `cancel` and closes the dashboard right away.
+pub async fn run(mut events: UnboundedReceiver<ProgressEvent>, cancel: Arc<Notify>) {
+    let mut terminal = ratatui::init();
+    let mut dashboard = Dashboard {
+        started: Instant::now(),
+        buckets: vec![],
+        active: BTreeMap::new(),
+        errors: VecDeque::new(),
+        finished: VecDeque::new(),
+        transferred: 0,
+    };
+    let mut redraw_interval = tokio::time::interval(REDRAW_INTERVAL);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => dashboard.apply(event),
+                None => break,
+            },
+            _ = redraw_interval.tick() => {
+                if quit_requested() {
+                    cancel.notify_one();
+                    break;
+                }
+                redraw(&mut terminal, &mut dashboard);
+            }
+        }
+    }
+    redraw(&mut terminal, &mut dashboard);
+    ratatui::restore();
+}
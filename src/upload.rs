@@ -0,0 +1,1100 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::SdkError,
+    operation::get_object::GetObjectOutput,
+    primitives::{ByteStream, DateTime, DateTimeFormat},
+    types::{
+        ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart, MultipartUpload,
+        ObjectCannedAcl, RequestPayer, StorageClass,
+    },
+    Client,
+};
+use bytes::Bytes;
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+    time::Instant,
+};
+
+use tracing::{info, warn, Instrument};
+
+use crate::{checksum::with_checksum, parse_size};
+
+/// Smallest part size S3 accepts (except for the last part), and the default part size and
+/// multipart threshold.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+const MAX_PARTS: usize = 10000;
+/// Largest object a single `PutObject` can upload.
+pub const MAX_SINGLEPART_SIZE: usize = 5 * 1024 * 1024 * 1024;
+
+/// A part size used for objects smaller than `below`, or for any object when `below` is unset.
+#[derive(Clone)]
+pub struct PartSizeTier {
+    condition: String,
+    below: Option<i64>,
+    part_size: usize,
+}
+
+fn invalid_entry<T>(variable: &str, entry: &str) -> T {
+    panic!("Invalid {} entry: {}", variable, entry)
+}
+
+/// Parses tiers like `<1GiB:16MiB,<100GiB:64MiB,default:256MiB`. The first matching tier wins.
+pub fn parse_part_size_tiers(tiers: &str) -> Vec<PartSizeTier> {
+    tiers
+        .split(',')
+        .map(|tier| {
+            let (condition, part_size) = tier
+                .split_once(':')
+                .unwrap_or_else(|| invalid_entry("PART_SIZE_TIERS", tier));
+            let part_size = parse_size(part_size)
+                .unwrap_or_else(|| invalid_entry("PART_SIZE_TIERS", tier))
+                as usize;
+            if part_size < MIN_PART_SIZE {
+                panic!(
+                    "Part sizes in PART_SIZE_TIERS must be at least 5MiB: {}",
+                    tier
+                );
+            }
+            let condition = condition.trim();
+            let below = match condition {
+                "default" => None,
+                _ => Some(
+                    condition
+                        .strip_prefix('<')
+                        .and_then(parse_size)
+                        .unwrap_or_else(|| invalid_entry("PART_SIZE_TIERS", tier)),
+                ),
+            };
+            PartSizeTier {
+                condition: condition.to_string(),
+                below,
+                part_size,
+            }
+        })
+        .collect()
+}
+
+/// A storage class used for objects matching the size condition (`<size`, `>=size` or
+/// `default`).
+#[derive(Clone)]
+pub struct StorageClassRule {
+    at_least: Option<i64>,
+    below: Option<i64>,
+    storage_class: StorageClass,
+}
+
+impl StorageClassRule {
+    /// A rule that matches objects of any size.
+    pub fn any_size(storage_class: StorageClass) -> Self {
+        StorageClassRule {
+            at_least: None,
+            below: None,
+            storage_class,
+        }
+    }
+}
+
+/// Parses rules like `>=128KB:STANDARD_IA,default:STANDARD`. The first matching rule wins.
+pub fn parse_storage_class_rules(rules: &str) -> Vec<StorageClassRule> {
+    rules
+        .split(',')
+        .map(|rule| {
+            let (condition, storage_class) = rule
+                .split_once(':')
+                .unwrap_or_else(|| invalid_entry("STORAGE_CLASS_BY_SIZE", rule));
+            if !StorageClass::values().contains(&storage_class) {
+                panic!(
+                    "Invalid storage class in STORAGE_CLASS_BY_SIZE: {} (expected one of {})",
+                    storage_class,
+                    StorageClass::values().join(", ")
+                );
+            }
+            let condition = condition.trim();
+            let (at_least, below) = if condition == "default" {
+                (None, None)
+            } else if let Some(size) = condition.strip_prefix(">=") {
+                (
+                    Some(
+                        parse_size(size)
+                            .unwrap_or_else(|| invalid_entry("STORAGE_CLASS_BY_SIZE", rule)),
+                    ),
+                    None,
+                )
+            } else if let Some(size) = condition.strip_prefix('<') {
+                (
+                    None,
+                    Some(
+                        parse_size(size)
+                            .unwrap_or_else(|| invalid_entry("STORAGE_CLASS_BY_SIZE", rule)),
+                    ),
+                )
+            } else {
+                invalid_entry("STORAGE_CLASS_BY_SIZE", rule)
+            };
+            StorageClassRule {
+                at_least,
+                below,
+                storage_class: StorageClass::from(storage_class),
+            }
+        })
+        .collect()
+}
+
+/// Translates source storage class names into ones the destination accepts, for copies between
+/// providers whose class names differ.
+#[derive(Clone)]
+pub struct StorageClassMap {
+    classes: HashMap<String, StorageClass>,
+    default: Option<StorageClass>,
+}
+
+impl StorageClassMap {
+    /// Reads `source<TAB>destination` lines. A `*` source sets the class for unmapped classes,
+    /// which otherwise keep their source name.
+    pub fn load(path: &str) -> Self {
+        let mut map = Self {
+            classes: HashMap::new(),
+            default: None,
+        };
+        for line in fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+            .lines()
+            .filter(|line| !line.is_empty())
+        {
+            let (source, destination) = line
+                .split_once('\t')
+                .unwrap_or_else(|| invalid_entry("STORAGE_CLASS_MAP", line));
+            let destination = StorageClass::from(destination);
+            match source {
+                "*" => map.default = Some(destination),
+                _ => {
+                    map.classes.insert(source.to_string(), destination);
+                }
+            }
+        }
+        map
+    }
+
+    pub fn contains(&self, source: &str) -> bool {
+        self.classes.contains_key(source)
+    }
+
+    fn translate(&self, source: &str) -> StorageClass {
+        self.classes
+            .get(source)
+            .or(self.default.as_ref())
+            .cloned()
+            .unwrap_or_else(|| StorageClass::from(source))
+    }
+}
+
+/// Caps the bytes that uploads hold in memory at once, across every object and part that shares
+/// it. Permits are KiB so that a 5GiB part fits in one request.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    semaphore: Arc<Semaphore>,
+    kib: u32,
+}
+
+impl MemoryBudget {
+    pub fn new(bytes: usize) -> Self {
+        let kib = bytes
+            .div_ceil(1024)
+            .clamp(1, (u32::MAX as usize).min(Semaphore::MAX_PERMITS)) as u32;
+        MemoryBudget {
+            semaphore: Arc::new(Semaphore::new(kib as usize)),
+            kib,
+        }
+    }
+
+    /// Waits until `bytes` more can be held, and holds them until the permit is dropped. A
+    /// request larger than the whole budget takes all of it, waiting for every other holder to
+    /// finish rather than forever.
+    pub async fn reserve(&self, bytes: usize) -> OwnedSemaphorePermit {
+        let kib = (bytes.div_ceil(1024).min(self.kib as usize) as u32).max(1);
+        self.semaphore
+            .clone()
+            .acquire_many_owned(kib)
+            .await
+            .unwrap()
+    }
+}
+
+/// Caps the requests of one kind in flight at once, across every bucket, object and part that
+/// shares it. The object and part concurrencies limit how many are started; this limits how many
+/// of those are sent at once.
+#[derive(Clone)]
+pub struct RequestLimit {
+    semaphore: Arc<Semaphore>,
+}
+
+impl RequestLimit {
+    pub fn new(requests: usize) -> Self {
+        RequestLimit {
+            semaphore: Arc::new(Semaphore::new(requests.max(1))),
+        }
+    }
+
+    /// Waits for a free request, and holds it until the permit is dropped.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.semaphore.clone().acquire_owned().await.unwrap()
+    }
+}
+
+/// A multipart upload created on the destination.
+#[derive(Clone)]
+struct OpenUpload {
+    client: Client,
+    bucket: String,
+    key: String,
+}
+
+/// Multipart uploads created on the destination and not yet completed or aborted, by upload
+/// ID. A run stopped in the middle of a copy drops it with its upload open, and aborts what is
+/// left here instead.
+#[derive(Clone, Default)]
+pub struct OpenUploads {
+    uploads: Arc<Mutex<HashMap<String, OpenUpload>>>,
+}
+
+impl OpenUploads {
+    pub(crate) fn open(&self, client: &Client, bucket: &str, key: &str, upload_id: &str) {
+        self.uploads.lock().unwrap().insert(
+            upload_id.to_string(),
+            OpenUpload {
+                client: client.clone(),
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            },
+        );
+    }
+
+    pub(crate) fn close(&self, upload_id: &str) {
+        self.uploads.lock().unwrap().remove(upload_id);
+    }
+
+    /// Aborts every upload still open.
+    pub async fn abort_all(&self, options: &UploadOptions) {
+        let uploads = self.uploads.lock().unwrap().clone();
+        for (upload_id, upload) in uploads {
+            abort_upload(
+                &upload.client,
+                &upload.bucket,
+                &upload.key,
+                &upload_id,
+                options,
+            )
+            .await;
+        }
+    }
+}
+
+/// Settings shared by every upload to the destination.
+#[derive(Clone)]
+pub struct UploadOptions {
+    pub request_payer: Option<RequestPayer>,
+    pub if_none_match: bool,
+    pub acl: Option<ObjectCannedAcl>,
+    pub checksums: bool,
+    pub part_size: usize,
+    pub multipart_threshold: usize,
+    pub part_size_tiers: Vec<PartSizeTier>,
+    pub max_concurrent_parts: usize,
+    pub adaptive_part_concurrency: bool,
+    pub part_attempts: u32,
+    pub storage_class_rules: Vec<StorageClassRule>,
+    pub preserve_storage_class: bool,
+    pub storage_class_map: Option<StorageClassMap>,
+    pub verify_size: bool,
+    pub memory_budget: Option<MemoryBudget>,
+    /// Requests carrying object data: uploads of whole objects and parts, and server-side copies.
+    pub transfer_limit: Option<RequestLimit>,
+    /// Requests reading an object's metadata: `HeadObject` and `GetObjectTagging`. Limited apart
+    /// from the transfers so that neither waits for the other.
+    pub head_limit: Option<RequestLimit>,
+    pub open_uploads: OpenUploads,
+    /// Fails the object instead of dropping an `Expires` header that is not an HTTP date.
+    pub strict: bool,
+}
+
+/// Defaults of the `s3copy` binary: 5MiB parts and threshold, 8 parts in flight, 5 attempts per
+/// part, the bucket's default storage class and no checksums, ACL or conditional writes.
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions {
+            request_payer: None,
+            if_none_match: false,
+            acl: None,
+            checksums: false,
+            part_size: MIN_PART_SIZE,
+            multipart_threshold: MIN_PART_SIZE,
+            part_size_tiers: vec![],
+            max_concurrent_parts: 8,
+            adaptive_part_concurrency: false,
+            part_attempts: 5,
+            storage_class_rules: vec![],
+            preserve_storage_class: false,
+            storage_class_map: None,
+            verify_size: false,
+            memory_budget: None,
+            transfer_limit: None,
+            head_limit: None,
+            open_uploads: OpenUploads::default(),
+            strict: false,
+        }
+    }
+}
+
+impl UploadOptions {
+    /// Whether an object of this size is uploaded in parts rather than with one `PutObject`.
+    /// Objects of unknown size or over the `PutObject` limit always are, whatever the threshold.
+    pub fn is_multipart(&self, object_size: Option<i64>) -> bool {
+        object_size.is_none_or(|object_size| {
+            object_size as usize > self.multipart_threshold
+                || object_size as usize > MAX_SINGLEPART_SIZE
+        })
+    }
+
+    /// Storage class from the first rule matching the object size. Without a matching rule, the
+    /// source's storage class (translated by the map, which implies preserving it) when it is
+    /// preserved, and otherwise the bucket default.
+    pub fn storage_class(&self, object_size: i64, source: Option<&str>) -> Option<StorageClass> {
+        self.storage_class_rules
+            .iter()
+            .find(|rule| {
+                rule.at_least.is_none_or(|at_least| object_size >= at_least)
+                    && rule.below.is_none_or(|below| object_size < below)
+            })
+            .map(|rule| rule.storage_class.clone())
+            .or_else(|| {
+                source
+                    .filter(|_| self.preserve_storage_class || self.storage_class_map.is_some())
+                    .map(|source| match &self.storage_class_map {
+                        Some(map) => map.translate(source),
+                        None => StorageClass::from(source),
+                    })
+            })
+    }
+
+    /// Reserves `bytes` of the memory budget, if there is one.
+    async fn reserve_memory(&self, bytes: usize) -> Option<OwnedSemaphorePermit> {
+        match &self.memory_budget {
+            Some(budget) => Some(budget.reserve(bytes).await),
+            None => None,
+        }
+    }
+
+    /// Waits for a transfer of the limit, if there is one. Only held while a request sends data,
+    /// so that parts waiting for it never hold up the objects they belong to.
+    pub(crate) async fn reserve_transfer(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.transfer_limit {
+            Some(limit) => Some(limit.acquire().await),
+            None => None,
+        }
+    }
+
+    /// Waits for a metadata request of the limit, if there is one.
+    pub(crate) async fn reserve_head(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.head_limit {
+            Some(limit) => Some(limit.acquire().await),
+            None => None,
+        }
+    }
+}
+
+pub enum UploadOutcome {
+    Uploaded,
+    AlreadyPresent,
+}
+
+fn is_precondition_failed<E>(e: &SdkError<E, HttpResponse>) -> bool {
+    e.raw_response()
+        .is_some_and(|response| response.status().as_u16() == 412)
+}
+
+/// Part size from the first matching tier (or `default`), raised if needed so that the object
+/// fits in `MAX_PARTS` parts.
+pub fn part_size(object_size: usize, default: usize, tiers: &[PartSizeTier]) -> usize {
+    let part_size = match tiers
+        .iter()
+        .find(|tier| tier.below.is_none_or(|below| (object_size as i64) < below))
+    {
+        Some(tier) => {
+            info!(
+                "Part size tier: {} ({} bytes)",
+                tier.condition, tier.part_size
+            );
+            tier.part_size
+        }
+        None => default,
+    };
+    part_size.max(object_size.div_ceil(MAX_PARTS))
+}
+
+/// The source's `Expires` header as a date. Values that are not HTTP dates (such as `0`, which
+/// caches treat as already expired) cannot be set through the SDK and are dropped.
+pub fn expires(expires: Option<&str>) -> Option<DateTime> {
+    expires.and_then(|expires| DateTime::from_str(expires, DateTimeFormat::HttpDate).ok())
+}
+
+/// [`expires`], failing instead of dropping the header when `strict` is set.
+pub(crate) fn checked_expires(
+    value: Option<&str>,
+    key: &str,
+    options: &UploadOptions,
+) -> Result<Option<DateTime>, String> {
+    match (value, expires(value)) {
+        (Some(value), None) if options.strict => Err(format!(
+            "Expires of {} is not an HTTP date and cannot be preserved: {}",
+            key, value
+        )),
+        (_, expires) => Ok(expires),
+    }
+}
+
+/// The source object's user metadata with `metadata` added on top, or `None` when both are
+/// empty so that no metadata headers are sent.
+pub(crate) fn with_user_metadata(
+    user_metadata: Option<&HashMap<String, String>>,
+    metadata: Option<HashMap<String, String>>,
+) -> Option<HashMap<String, String>> {
+    let mut user_metadata = user_metadata.cloned().unwrap_or_default();
+    user_metadata.extend(metadata.unwrap_or_default());
+    (!user_metadata.is_empty()).then_some(user_metadata)
+}
+
+/// Checks that the destination object has the source's size, catching a body that ended early.
+pub(crate) async fn verify_size(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    expected: Option<i64>,
+    options: &UploadOptions,
+) -> Result<(), String> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let _head = options.reserve_head().await;
+    let size = client
+        .head_object()
+        .set_request_payer(options.request_payer.clone())
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| format!("{:?}", e))?
+        .content_length
+        .unwrap_or_default();
+    if size != expected {
+        return Err(format!(
+            "Size mismatch for {}: source {} bytes, destination {} bytes",
+            key, expected, size
+        ));
+    }
+    Ok(())
+}
+
+/// Uploads the object with a single `PutObject`.
+///
+/// The body is read into memory first so that the SDK can retry the request; a streaming body
+/// cannot be rewound after a failure partway through.
+pub async fn singlepart_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    object: GetObjectOutput,
+    metadata: Option<HashMap<String, String>>,
+    tagging: Option<String>,
+    options: &UploadOptions,
+) -> Result<UploadOutcome, String> {
+    let object_size = object.content_length;
+    let mut request = client
+        .put_object()
+        .set_request_payer(options.request_payer.clone())
+        .bucket(bucket)
+        .key(key)
+        .set_if_none_match(options.if_none_match.then(|| "*".to_string()))
+        .set_acl(options.acl.clone())
+        .set_storage_class(options.storage_class(
+            object.content_length.unwrap_or_default(),
+            object.storage_class().map(StorageClass::as_str),
+        ))
+        .set_content_type(object.content_type().map(str::to_string))
+        .set_content_encoding(object.content_encoding().map(str::to_string))
+        .set_cache_control(object.cache_control().map(str::to_string))
+        .set_content_disposition(object.content_disposition().map(str::to_string))
+        .set_expires(checked_expires(object.expires_string(), key, options)?)
+        .set_metadata(with_user_metadata(object.metadata.as_ref(), metadata))
+        .set_tagging(tagging);
+    if options.checksums {
+        request = with_checksum(request, &object);
+    }
+    // Held until the request has been sent, since the whole body is kept in memory until then.
+    let _memory = options
+        .reserve_memory(object_size.unwrap_or_default() as usize)
+        .await;
+    let body = object
+        .body
+        .collect()
+        .await
+        .map_err(|e| format!("{:?}", e))?
+        .into_bytes();
+    let transfer = options.reserve_transfer().await;
+    let sent = request.body(ByteStream::from(body)).send().await;
+    drop(transfer);
+    match sent {
+        Ok(_) => {
+            if options.verify_size {
+                verify_size(client, bucket, key, object_size, options).await?;
+            }
+            Ok(UploadOutcome::Uploaded)
+        }
+        Err(e) if options.if_none_match && is_precondition_failed(&e) => {
+            Ok(UploadOutcome::AlreadyPresent)
+        }
+        Err(e) => Err(format!("{:?}", e)),
+    }
+}
+
+/// Uploads the part, retrying it up to `options.part_attempts` times in all and waiting twice as
+/// long after each failure, so that an error outlasting the SDK's own retries does not fail the
+/// whole object.
+async fn upload_part(
+    client: Client,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    part_number: i32,
+    body: Vec<u8>,
+    options: UploadOptions,
+) -> Result<CompletedPart, String> {
+    let body = Bytes::from(body);
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    let output = loop {
+        let transfer = options.reserve_transfer().await;
+        let sent = client
+            .upload_part()
+            .set_request_payer(options.request_payer.clone())
+            .bucket(&bucket)
+            .key(&key)
+            .upload_id(&upload_id)
+            .part_number(part_number)
+            .set_checksum_algorithm(options.checksums.then_some(ChecksumAlgorithm::Crc32))
+            .body(ByteStream::from(body.clone()))
+            .send()
+            .await;
+        // Released before waiting to retry.
+        drop(transfer);
+        match sent {
+            Ok(output) => break output,
+            Err(e) if attempt < options.part_attempts => {
+                warn!(
+                    "Part {} of {} failed (attempt {}/{}), retrying in {}s: {:?}",
+                    part_number,
+                    key,
+                    attempt,
+                    options.part_attempts,
+                    delay.as_secs(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Part {} of {} failed after {} attempts: {:?}",
+                    part_number, key, attempt, e
+                ))
+            }
+        }
+    };
+    let e_tag = output.e_tag.ok_or_else(|| {
+        format!(
+            "Part {} of {} was uploaded without an ETag, so the upload cannot be completed",
+            part_number, key
+        )
+    })?;
+    Ok(CompletedPart::builder()
+        .part_number(part_number)
+        .e_tag(e_tag)
+        .set_checksum_crc32(output.checksum_crc32)
+        .build())
+}
+
+/// Aborts a multipart upload that will not be completed so that its parts stop being billed. A
+/// failure to abort is only logged, since the copy has already ended; the upload is then left
+/// for `multipart`.
+pub(crate) async fn abort_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    options: &UploadOptions,
+) {
+    options.open_uploads.close(upload_id);
+    match client
+        .abort_multipart_upload()
+        .set_request_payer(options.request_payer.clone())
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .send()
+        .await
+    {
+        Ok(_) => info!("Aborted multipart upload: {} ({})", key, upload_id),
+        Err(e) => warn!(
+            "Failed to abort multipart upload: {} ({}): {:?}",
+            key, upload_id, e
+        ),
+    }
+}
+
+/// Lists the bucket's incomplete multipart uploads, following the key and upload ID markers.
+pub async fn list_multipart_uploads(
+    client: &Client,
+    bucket: &str,
+    request_payer: Option<RequestPayer>,
+) -> Result<Vec<MultipartUpload>, String> {
+    let mut uploads = vec![];
+    let mut key_marker = None;
+    let mut upload_id_marker = None;
+    loop {
+        let output = client
+            .list_multipart_uploads()
+            .set_request_payer(request_payer.clone())
+            .bucket(bucket)
+            .set_key_marker(key_marker)
+            .set_upload_id_marker(upload_id_marker)
+            .send()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        uploads.extend(output.uploads.unwrap_or_default());
+        if !output.is_truncated.unwrap_or_default() {
+            return Ok(uploads);
+        }
+        key_marker = output.next_key_marker;
+        upload_id_marker = output.next_upload_id_marker;
+    }
+}
+
+/// Sorts the parts by part number and fails unless they are exactly 1 to `uploaded_parts`, so
+/// that a mis-assembled upload is never completed into a corrupt object.
+pub(crate) fn validate_parts(
+    key: &str,
+    parts: &mut [CompletedPart],
+    uploaded_parts: usize,
+) -> Result<(), String> {
+    parts.sort_by_key(|part| part.part_number);
+    if parts.len() != uploaded_parts {
+        return Err(format!(
+            "{} parts of {} were completed, but {} were uploaded",
+            parts.len(),
+            key,
+            uploaded_parts
+        ));
+    }
+    for (i, part) in parts.iter().enumerate() {
+        let expected = i as i32 + 1;
+        if part.part_number != Some(expected) {
+            return Err(format!(
+                "Parts of {} are not numbered 1 to {}: found {:?} where {} was expected",
+                key, uploaded_parts, part.part_number, expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Number of parts of one object in flight. When adaptive, it starts at one and grows while the
+/// combined throughput keeps improving, and shrinks when it falls, which is how throttling and a
+/// saturated link show up. It never exceeds `max`.
+struct PartConcurrency {
+    semaphore: Arc<Semaphore>,
+    adaptive: bool,
+    limit: usize,
+    max: usize,
+    peak: usize,
+    /// Permits to drop instead of releasing, after the limit has been lowered.
+    excess: usize,
+    best_throughput: f64,
+}
+
+impl PartConcurrency {
+    fn new(max: usize, adaptive: bool) -> Self {
+        let limit = if adaptive { 1 } else { max };
+        PartConcurrency {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            adaptive,
+            limit,
+            max,
+            peak: limit,
+            excess: 0,
+            best_throughput: 0.0,
+        }
+    }
+
+    /// Records a finished part and releases its permit.
+    fn finish(&mut self, permit: OwnedSemaphorePermit, part_bytes: usize, elapsed: Duration) {
+        if !self.adaptive {
+            return;
+        }
+        if self.excess > 0 {
+            self.excess -= 1;
+            permit.forget();
+        }
+        let throughput = part_bytes as f64 / elapsed.as_secs_f64().max(0.001) * self.limit as f64;
+        if throughput > self.best_throughput * 1.1 && self.limit < self.max {
+            self.best_throughput = throughput;
+            self.limit += 1;
+            self.peak = self.peak.max(self.limit);
+            self.semaphore.add_permits(1);
+        } else if throughput < self.best_throughput * 0.7 && self.limit > 1 {
+            self.limit -= 1;
+            self.excess += 1 - self.semaphore.forget_permits(1);
+        } else {
+            self.best_throughput = self.best_throughput.max(throughput);
+        }
+    }
+}
+
+/// Uploads the object in parts, streaming it from the source and uploading each part as soon
+/// as it has been read. At most `max_concurrent_parts` parts are in flight; reading waits for
+/// one of them to finish, which bounds memory to that many parts.
+pub async fn multipart_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    mut object: GetObjectOutput,
+    metadata: Option<HashMap<String, String>>,
+    tagging: Option<String>,
+    options: &UploadOptions,
+) -> Result<UploadOutcome, String> {
+    let object_size = object.content_length;
+    let part_size = part_size(
+        object.content_length.unwrap_or_default() as usize,
+        options.part_size,
+        &options.part_size_tiers,
+    );
+    if options.checksums {
+        info!("Checksum: recomputed CRC32");
+    }
+    let upload_id = client
+        .create_multipart_upload()
+        .set_request_payer(options.request_payer.clone())
+        .bucket(bucket)
+        .key(key)
+        .set_acl(options.acl.clone())
+        .set_storage_class(options.storage_class(
+            object.content_length.unwrap_or_default(),
+            object.storage_class().map(StorageClass::as_str),
+        ))
+        .set_content_type(object.content_type().map(str::to_string))
+        .set_content_encoding(object.content_encoding().map(str::to_string))
+        .set_cache_control(object.cache_control().map(str::to_string))
+        .set_content_disposition(object.content_disposition().map(str::to_string))
+        .set_expires(checked_expires(object.expires_string(), key, options)?)
+        .set_metadata(with_user_metadata(
+            object.metadata.as_ref(),
+            metadata.clone(),
+        ))
+        .set_tagging(tagging.clone())
+        .set_checksum_algorithm(options.checksums.then_some(ChecksumAlgorithm::Crc32))
+        .send()
+        .await
+        .map_err(|e| format!("{:?}", e))?
+        .upload_id
+        .ok_or_else(|| format!("No upload ID was returned for {}", key))?;
+    options.open_uploads.open(client, bucket, key, &upload_id);
+
+    let concurrency = Arc::new(Mutex::new(PartConcurrency::new(
+        options.max_concurrent_parts,
+        options.adaptive_part_concurrency,
+    )));
+    let semaphore = concurrency.lock().unwrap().semaphore.clone();
+    // Dropping the set, as when the run is stopped, aborts the parts still in flight.
+    let mut upload_tasks: JoinSet<Result<CompletedPart, String>> = JoinSet::new();
+    let mut uploaded_parts = 0;
+    let completed_parts = async {
+        let mut buffer = Vec::with_capacity(part_size);
+        let mut memory = None;
+        loop {
+            // The budget is reserved before the part is read, and released once it is uploaded.
+            if memory.is_none() {
+                memory = options.reserve_memory(part_size).await;
+            }
+            let chunk = object
+                .body
+                .try_next()
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+            if let Some(chunk) = &chunk {
+                buffer.extend_from_slice(chunk);
+            }
+            // A chunk can hold the end of one part and the start of the next, or several parts,
+            // so the buffer is split at the part size.
+            while buffer.len() >= part_size || (chunk.is_none() && !buffer.is_empty()) {
+                if memory.is_none() {
+                    memory = options.reserve_memory(part_size).await;
+                }
+                let permit = semaphore.clone().acquire_owned().await.unwrap();
+                let rest = buffer.split_off(part_size.min(buffer.len()));
+                let body = std::mem::replace(&mut buffer, rest);
+                let part_bytes = body.len();
+                uploaded_parts += 1;
+                let part = upload_part(
+                    client.clone(),
+                    bucket.to_string(),
+                    key.to_string(),
+                    upload_id.clone(),
+                    uploaded_parts as i32,
+                    body,
+                    options.clone(),
+                );
+                let concurrency = concurrency.clone();
+                let memory = memory.take();
+                // The task would otherwise lose the span of the bucket in its retry logs.
+                upload_tasks.spawn(
+                    async move {
+                        let started = Instant::now();
+                        let part = part.await;
+                        drop(memory);
+                        concurrency
+                            .lock()
+                            .unwrap()
+                            .finish(permit, part_bytes, started.elapsed());
+                        part
+                    }
+                    .in_current_span(),
+                );
+            }
+            if chunk.is_none() {
+                break;
+            }
+        }
+
+        let mut completed_parts = Vec::with_capacity(uploaded_parts);
+        while let Some(part) = upload_tasks.join_next().await {
+            completed_parts.push(part.map_err(|e| e.to_string())??);
+        }
+        validate_parts(key, &mut completed_parts, uploaded_parts)?;
+        Ok::<Vec<CompletedPart>, String>(completed_parts)
+    }
+    .await;
+    let completed_parts = match completed_parts {
+        Ok(completed_parts) => completed_parts,
+        Err(e) => {
+            // The parts still in flight would only fail once the upload is aborted.
+            upload_tasks.abort_all();
+            abort_upload(client, bucket, key, &upload_id, options).await;
+            return Err(e);
+        }
+    };
+    if completed_parts.is_empty() {
+        // An empty body of unknown size yields no part, and an upload cannot be completed without
+        // one, so the empty object is put instead.
+        abort_upload(client, bucket, key, &upload_id, options).await;
+        object.body = ByteStream::from_static(b"");
+        return singlepart_upload(client, bucket, key, object, metadata, tagging, options).await;
+    }
+    if options.adaptive_part_concurrency {
+        let concurrency = concurrency.lock().unwrap();
+        info!(
+            "Part concurrency: {} (peak {})",
+            concurrency.limit, concurrency.peak
+        );
+    }
+
+    match client
+        .complete_multipart_upload()
+        .set_request_payer(options.request_payer.clone())
+        .bucket(bucket)
+        .key(key)
+        .upload_id(&upload_id)
+        .set_if_none_match(options.if_none_match.then(|| "*".to_string()))
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(completed_parts))
+                .build(),
+        )
+        .send()
+        .await
+    {
+        Ok(_) => {
+            options.open_uploads.close(&upload_id);
+            if options.verify_size {
+                verify_size(client, bucket, key, object_size, options).await?;
+            }
+            Ok(UploadOutcome::Uploaded)
+        }
+        Err(e) if options.if_none_match && is_precondition_failed(&e) => {
+            abort_upload(client, bucket, key, &upload_id, options).await;
+            Ok(UploadOutcome::AlreadyPresent)
+        }
+        Err(e) => {
+            abort_upload(client, bucket, key, &upload_id, options).await;
+            Err(format!("{:?}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_size_is_raised_to_fit_in_the_part_limit() {
+        assert_eq!(part_size(1, MIN_PART_SIZE, &[]), MIN_PART_SIZE);
+        assert_eq!(
+            part_size(MAX_PARTS * MIN_PART_SIZE, MIN_PART_SIZE, &[]),
+            MIN_PART_SIZE
+        );
+        assert_eq!(
+            part_size(MAX_PARTS * MIN_PART_SIZE + 1, MIN_PART_SIZE, &[]),
+            MIN_PART_SIZE + 1
+        );
+    }
+
+    #[test]
+    fn first_matching_part_size_tier_wins() {
+        let tiers = parse_part_size_tiers("<1GiB:16MiB, <100GiB:64MiB,default:256MiB");
+        assert_eq!(part_size(1, MIN_PART_SIZE, &tiers), 16 << 20);
+        assert_eq!(part_size((1 << 30) - 1, MIN_PART_SIZE, &tiers), 16 << 20);
+        assert_eq!(part_size(1 << 30, MIN_PART_SIZE, &tiers), 64 << 20);
+        assert_eq!(part_size(100 << 30, MIN_PART_SIZE, &tiers), 256 << 20);
+        let tiers = parse_part_size_tiers("<1GiB:16MiB");
+        assert_eq!(part_size(1 << 30, MIN_PART_SIZE, &tiers), MIN_PART_SIZE);
+    }
+
+    #[test]
+    #[should_panic(expected = "Part sizes in PART_SIZE_TIERS must be at least 5MiB")]
+    fn part_size_tiers_below_the_minimum_are_rejected() {
+        parse_part_size_tiers("<1GiB:1MiB");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid PART_SIZE_TIERS entry: >1GiB:16MiB")]
+    fn part_size_tiers_need_a_below_condition() {
+        parse_part_size_tiers(">1GiB:16MiB");
+    }
+
+    #[test]
+    fn first_matching_storage_class_rule_wins_over_the_source_class() {
+        let options = UploadOptions {
+            storage_class_rules: parse_storage_class_rules(
+                "<128KiB:STANDARD, >=1GiB:GLACIER_IR,default:STANDARD_IA",
+            ),
+            preserve_storage_class: true,
+            ..Default::default()
+        };
+        for (size, storage_class) in [
+            (0, StorageClass::Standard),
+            ((128 << 10) - 1, StorageClass::Standard),
+            (128 << 10, StorageClass::StandardIa),
+            (1 << 30, StorageClass::GlacierIr),
+        ] {
+            assert_eq!(
+                options.storage_class(size, Some("ONEZONE_IA")),
+                Some(storage_class),
+                "{}",
+                size
+            );
+        }
+
+        let options = UploadOptions {
+            storage_class_rules: parse_storage_class_rules(">=1GiB:GLACIER_IR"),
+            preserve_storage_class: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.storage_class(1, Some("ONEZONE_IA")),
+            Some(StorageClass::OnezoneIa)
+        );
+        let options = UploadOptions {
+            preserve_storage_class: false,
+            ..options
+        };
+        assert_eq!(options.storage_class(1, Some("ONEZONE_IA")), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid storage class in STORAGE_CLASS_BY_SIZE: COLD")]
+    fn unknown_storage_classes_are_rejected() {
+        parse_storage_class_rules(">=1GiB:COLD");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid STORAGE_CLASS_BY_SIZE entry: <=1GiB:STANDARD")]
+    fn unknown_size_conditions_are_rejected() {
+        parse_storage_class_rules("<=1GiB:STANDARD");
+    }
+
+    fn parts(numbers: &[i32]) -> Vec<CompletedPart> {
+        numbers
+            .iter()
+            .map(|&number| {
+                CompletedPart::builder()
+                    .part_number(number)
+                    .e_tag(format!("\"{}\"", number))
+                    .build()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parts_completed_out_of_order_are_sorted() {
+        let mut completed = parts(&[3, 1, 2]);
+        validate_parts("clip.mp4", &mut completed, 3).unwrap();
+        assert_eq!(
+            completed
+                .iter()
+                .map(|part| part.part_number.unwrap())
+                .collect::<Vec<i32>>(),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn missing_duplicate_or_extra_parts_are_rejected() {
+        let error = validate_parts("clip.mp4", &mut parts(&[2, 1]), 3).unwrap_err();
+        assert!(error.contains("2 parts of clip.mp4 were completed, but 3 were uploaded"));
+        let error = validate_parts("clip.mp4", &mut parts(&[1, 3, 1]), 3).unwrap_err();
+        assert!(
+            error.contains("found Some(1) where 2 was expected"),
+            "{}",
+            error
+        );
+        let error = validate_parts("clip.mp4", &mut parts(&[1, 2, 4]), 3).unwrap_err();
+        assert!(
+            error.contains("found Some(4) where 3 was expected"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn objects_over_the_threshold_or_of_unknown_size_are_multipart() {
+        let options = UploadOptions {
+            multipart_threshold: 8,
+            ..Default::default()
+        };
+        assert!(!options.is_multipart(Some(8)));
+        assert!(options.is_multipart(Some(9)));
+        assert!(options.is_multipart(None));
+        let options = UploadOptions {
+            multipart_threshold: usize::MAX,
+            ..Default::default()
+        };
+        assert!(!options.is_multipart(Some(MAX_SINGLEPART_SIZE as i64)));
+        assert!(options.is_multipart(Some(MAX_SINGLEPART_SIZE as i64 + 1)));
+    }
+}
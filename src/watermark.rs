@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, fs};
+
+use aws_sdk_s3::types::Object;
+use tracing::info;
+
+/// Per-bucket high-watermarks of source `last_modified` (in seconds since the epoch), stored as
+/// `bucket<TAB>seconds` lines.
+pub struct Watermarks {
+    path: String,
+    marks: BTreeMap<String, i64>,
+}
+
+impl Watermarks {
+    /// Reads the watermark file, treating a missing file as empty.
+    pub fn load(path: String) -> Self {
+        let marks = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| {
+                    let (bucket, secs) = line
+                        .split_once('\t')
+                        .unwrap_or_else(|| panic!("Invalid line in {}: {}", path, line));
+                    let secs = secs
+                        .parse()
+                        .unwrap_or_else(|_| panic!("Invalid line in {}: {}", path, line));
+                    (bucket.to_string(), secs)
+                })
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => panic!("Failed to read {}: {}", path, e),
+        };
+        Watermarks { path, marks }
+    }
+
+    /// Drops objects modified before the bucket's watermark and returns the newest
+    /// `last_modified` of the listing. Objects from the watermark's own second are kept, since
+    /// more objects may have been written in that second after the previous listing.
+    pub fn filter(&self, bucket: &str, objects: &mut Vec<Object>) -> Option<i64> {
+        let newest = objects
+            .iter()
+            .filter_map(|object| {
+                object
+                    .last_modified
+                    .map(|last_modified| last_modified.secs())
+            })
+            .max();
+        let Some(&watermark) = self.marks.get(bucket) else {
+            return newest;
+        };
+        let listed = objects.len();
+        objects.retain(|object| {
+            object
+                .last_modified
+                .is_none_or(|last_modified| last_modified.secs() >= watermark)
+        });
+        info!(
+            "Watermark: {} objects older than {} skipped",
+            listed - objects.len(),
+            watermark
+        );
+        newest
+    }
+
+    /// Raises the bucket's watermark to `newest` and writes the file.
+    pub fn advance(&mut self, bucket: &str, newest: i64) {
+        let watermark = self.marks.entry(bucket.to_string()).or_insert(newest);
+        *watermark = (*watermark).max(newest);
+        let contents = self
+            .marks
+            .iter()
+            .map(|(bucket, secs)| format!("{}\t{}\n", bucket, secs))
+            .collect::<String>();
+        fs::write(&self.path, contents)
+            .unwrap_or_else(|e| panic!("Failed to write {}: {}", self.path, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, panic, process};
+
+    use aws_sdk_s3::primitives::DateTime;
+
+    use super::*;
+
+    fn object(key: &str, secs: i64) -> Object {
+        Object::builder()
+            .key(key)
+            .last_modified(DateTime::from_secs(secs))
+            .build()
+    }
+
+    fn keys(objects: &[Object]) -> Vec<&str> {
+        objects.iter().map(|object| object.key().unwrap()).collect()
+    }
+
+    #[test]
+    fn objects_older_than_the_watermark_are_dropped() {
+        let path = env::temp_dir().join(format!("s3copy-watermarks-{}", process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+
+        let mut watermarks = Watermarks::load(path.clone());
+        let mut objects = vec![object("old", 100), object("new", 200)];
+        assert_eq!(watermarks.filter("logs", &mut objects), Some(200));
+        assert_eq!(keys(&objects), ["old", "new"]);
+        watermarks.advance("logs", 200);
+        watermarks.advance("photos", 50);
+        watermarks.advance("logs", 150);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "logs\t200\nphotos\t50\n"
+        );
+
+        let watermarks = Watermarks::load(path.clone());
+        fs::remove_file(&path).unwrap();
+        let mut objects = vec![
+            object("old", 199),
+            object("same second", 200),
+            Object::builder().key("undated").build(),
+            object("new", 300),
+        ];
+        assert_eq!(watermarks.filter("logs", &mut objects), Some(300));
+        assert_eq!(keys(&objects), ["same second", "undated", "new"]);
+        let mut objects = vec![object("other bucket", 10)];
+        assert_eq!(watermarks.filter("docs", &mut objects), Some(10));
+        assert_eq!(keys(&objects), ["other bucket"]);
+    }
+
+    #[test]
+    fn malformed_watermark_files_are_rejected() {
+        let path = env::temp_dir().join(format!("s3copy-bad-watermarks-{}", process::id()));
+        fs::write(&path, "logs 200\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let loaded = panic::catch_unwind(|| Watermarks::load(path.clone()));
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+}